@@ -1,15 +1,26 @@
 use super::{
     table::{Mode, Table, Writeable},
-    util::is_valid_screen_name,
+    util::{is_sorted_chunks, is_valid_screen_name, merge_sorted_chunks, sort_chunks},
     Error,
 };
-use chrono::{Duration, NaiveDate};
-use rocksdb::{DBIterator, IteratorMode, MergeOperands, Options, DB};
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use rocksdb::{
+    compaction_filter::{CompactionFilter, Decision},
+    compaction_filter_factory::{CompactionFilterContext, CompactionFilterFactory},
+    DBIterator, IteratorMode, MergeOperands, Options, DB,
+};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::convert::TryInto;
+use std::ffi::CStr;
+use std::fmt::Display;
 use std::marker::PhantomData;
 use std::path::Path;
-use std::sync::LazyLock;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock};
+
+const DAY_ID_LEN: usize = 2;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct AccountTableCounts {
@@ -17,8 +28,81 @@ pub struct AccountTableCounts {
     pub pair_count: u64,
 }
 
+/// Observation dates for an ID-screen name pair, with the exact first and last observation times
+/// when they are known.
+///
+/// The timestamps only cover observations that were imported with second precision, so the
+/// dates may extend beyond them.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Observations {
+    pub dates: Vec<NaiveDate>,
+    pub timestamps: Option<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+impl Observations {
+    pub fn new(dates: Vec<NaiveDate>, timestamps: Option<(DateTime<Utc>, DateTime<Utc>)>) -> Self {
+        Self { dates, timestamps }
+    }
+
+    pub fn first_seen(&self) -> Option<DateTime<Utc>> {
+        self.timestamps.map(|(first, _)| first)
+    }
+
+    pub fn last_seen(&self) -> Option<DateTime<Utc>> {
+        self.timestamps.map(|(_, last)| last)
+    }
+
+    /// Whether the pair was observed on or after the given date.
+    pub fn seen_since(&self, earliest: NaiveDate) -> bool {
+        self.dates.iter().any(|date| date >= &earliest)
+            || self
+                .last_seen()
+                .filter(|last| last.date_naive() >= earliest)
+                .is_some()
+    }
+}
+
+/// Determines which observation dates are kept when values for a pair are merged.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MergePolicy {
+    /// Keep every observation date.
+    #[default]
+    All,
+    /// Keep only the first and last observation dates.
+    Range,
+}
+
+impl MergePolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::All => "all",
+            Self::Range => "range",
+        }
+    }
+}
+
+impl Display for MergePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for MergePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(Self::All),
+            "range" => Ok(Self::Range),
+            other => Err(Error::InvalidMergePolicy(other.to_string())),
+        }
+    }
+}
+
 pub struct AccountTable<M> {
     db: DB,
+    compacting_ranges: Arc<AtomicBool>,
     mode: PhantomData<M>,
 }
 
@@ -34,7 +118,7 @@ impl<M> Table for AccountTable<M> {
         let mut id_count = 0;
         let mut last_id = 0;
 
-        let iter = self.db.iterator(IteratorMode::Start);
+        let iter = self.ordered_iterator(IteratorMode::Start);
 
         for result in iter {
             let (key, _) = result?;
@@ -56,13 +140,13 @@ impl<M> Table for AccountTable<M> {
 }
 
 impl<M> AccountTable<M> {
-    pub fn pairs(&self) -> PairIterator {
+    pub fn pairs(&self) -> PairIterator<'_> {
         PairIterator {
-            underlying: self.db.iterator(IteratorMode::Start),
+            underlying: self.ordered_iterator(IteratorMode::Start),
         }
     }
 
-    pub fn lookup(&self, id: u64) -> Result<HashMap<String, Vec<NaiveDate>>, Error> {
+    pub fn lookup(&self, id: u64) -> Result<HashMap<String, Observations>, Error> {
         let prefix = id_to_key_prefix(id);
         let iter = self.db.prefix_iterator(prefix);
         let mut results = HashMap::new();
@@ -72,8 +156,8 @@ impl<M> AccountTable<M> {
             let (next_id, next_screen_name) = key_to_pair(&key)?;
 
             if next_id == id {
-                let dates = value_to_dates(&value)?;
-                results.insert(next_screen_name.to_string(), dates);
+                let observations = value_to_observations(&value)?;
+                results.insert(next_screen_name.to_string(), observations);
             } else {
                 break;
             }
@@ -82,11 +166,19 @@ impl<M> AccountTable<M> {
         Ok(results)
     }
 
+    /// The observations for a single pair.
+    pub fn get(&self, id: u64, screen_name: &str) -> Result<Option<Observations>, Error> {
+        self.db
+            .get_pinned(pair_to_key(id, screen_name))?
+            .map(|value| value_to_observations(&value))
+            .transpose()
+    }
+
     pub fn limited_lookup(
         &self,
         id: u64,
         earliest: NaiveDate,
-    ) -> Result<HashMap<String, Vec<NaiveDate>>, Error> {
+    ) -> Result<HashMap<String, Observations>, Error> {
         let prefix = id_to_key_prefix(id);
         let iter = self.db.prefix_iterator(prefix);
         let mut results = HashMap::new();
@@ -96,9 +188,9 @@ impl<M> AccountTable<M> {
             let (next_id, next_screen_name) = key_to_pair(&key)?;
 
             if next_id == id {
-                let dates = value_to_dates(&value)?;
-                if dates.iter().any(|date| date >= &earliest) {
-                    results.insert(next_screen_name.to_string(), dates);
+                let observations = value_to_observations(&value)?;
+                if observations.seen_since(earliest) {
+                    results.insert(next_screen_name.to_string(), observations);
                 }
             } else {
                 break;
@@ -110,7 +202,7 @@ impl<M> AccountTable<M> {
 
     pub fn get_date_counts(&self) -> Result<Vec<(NaiveDate, u64)>, Error> {
         let mut map = HashMap::new();
-        let iter = self.db.iterator(IteratorMode::Start);
+        let iter = self.ordered_iterator(IteratorMode::Start);
 
         for result in iter {
             let (_, value) = result?;
@@ -130,7 +222,7 @@ impl<M> AccountTable<M> {
 
     pub fn get_most_screen_names(&self, k: usize) -> Result<Vec<(u64, Vec<String>)>, Error> {
         let mut queue = priority_queue::DoublePriorityQueue::with_capacity(k);
-        let iter = self.db.iterator(IteratorMode::Start);
+        let iter = self.ordered_iterator(IteratorMode::Start);
         let mut last_id = 0;
         let mut current: Vec<String> = vec![];
 
@@ -159,13 +251,66 @@ impl<M> AccountTable<M> {
 
         Ok(queue.into_descending_sorted_vec())
     }
+
+    /// Accounts with multiple screen names that differ only in case.
+    pub fn get_case_only_renames(&self) -> Result<Vec<(u64, Vec<String>)>, Error> {
+        let iter = self.ordered_iterator(IteratorMode::Start);
+        let mut results = vec![];
+        let mut last_id = None;
+        let mut current: Vec<String> = vec![];
+
+        for result in iter {
+            let (key, _) = result?;
+            let (id, screen_name) = key_to_pair(&key)?;
+
+            if last_id != Some(id) {
+                if let Some(last_id) = last_id {
+                    if is_case_only_rename(&current) {
+                        results.push((last_id, std::mem::take(&mut current)));
+                    }
+                }
+
+                current.clear();
+                last_id = Some(id);
+            }
+            current.push(screen_name.to_string());
+        }
+
+        if let Some(last_id) = last_id {
+            if is_case_only_rename(&current) {
+                results.push((last_id, current));
+            }
+        }
+
+        Ok(results)
+    }
+}
+
+fn is_case_only_rename(screen_names: &[String]) -> bool {
+    screen_names.len() > 1
+        && screen_names
+            .iter()
+            .all(|screen_name| screen_name.eq_ignore_ascii_case(&screen_names[0]))
 }
 
 impl<M: Mode> AccountTable<M> {
-    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
-        let mut options = Options::default();
-        options.create_if_missing(true);
-        options.set_merge_operator_associative("merge", merge);
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        merge_policy: MergePolicy,
+        mut options: Options,
+    ) -> Result<Self, Error> {
+        let compacting_ranges = Arc::new(AtomicBool::new(false));
+
+        let merge_compacting_ranges = compacting_ranges.clone();
+        options.set_merge_operator_associative("merge", move |key, existing_val, operands| {
+            let only_range = merge_policy == MergePolicy::Range
+                || merge_compacting_ranges.load(Ordering::SeqCst);
+            merge(key, existing_val, operands, only_range)
+        });
+        options.set_compaction_filter_factory(RangeFilterFactory {
+            merge_policy,
+            compacting_ranges: compacting_ranges.clone(),
+        });
 
         let db = if M::is_read_only() {
             DB::open_for_read_only(&options, path, true)?
@@ -175,6 +320,7 @@ impl<M: Mode> AccountTable<M> {
 
         Ok(Self {
             db,
+            compacting_ranges,
             mode: PhantomData,
         })
     }
@@ -182,12 +328,17 @@ impl<M: Mode> AccountTable<M> {
 
 impl AccountTable<Writeable> {
     pub fn insert(&self, id: u64, screen_name: &str, dates: Vec<NaiveDate>) -> Result<(), Error> {
-        if is_valid_screen_name(screen_name) {
-            let mut value = Vec::with_capacity(2 * dates.len());
+        self.insert_observations(id, screen_name, &Observations::new(dates, None))
+    }
 
-            for date in dates {
-                value.extend_from_slice(&date_to_day_id(&date)?.to_be_bytes());
-            }
+    pub fn insert_observations(
+        &self,
+        id: u64,
+        screen_name: &str,
+        observations: &Observations,
+    ) -> Result<(), Error> {
+        if is_valid_screen_name(screen_name) {
+            let value = observations_to_value(observations)?;
 
             self.db.merge(pair_to_key(id, screen_name), value)?;
 
@@ -203,45 +354,107 @@ impl AccountTable<Writeable> {
         Ok(self.db.delete(key)?)
     }
 
+    /// Reduce the observation dates for every pair to the first and last.
+    ///
+    /// This runs a full manual compaction during which both the merge operator and the compaction
+    /// filter only keep ranges. Under the range merge policy the filter is always active, so values
+    /// written before the policy was set are also reduced by background compactions.
     pub fn compact_ranges(&self) -> Result<(), Error> {
-        let iter = self.db.iterator(IteratorMode::Start);
+        self.compacting_ranges.store(true, Ordering::SeqCst);
+        self.db.compact_range(None::<&[u8]>, None::<&[u8]>);
+        self.compacting_ranges.store(false, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// Rewrite any values whose day IDs are not sorted and unique.
+    pub(crate) fn sort_values(&self) -> Result<usize, Error> {
+        let iter = self.ordered_iterator(IteratorMode::Start);
+        let mut count = 0;
 
         for result in iter {
             let (key, value) = result?;
-            let mut dates = value_to_dates(&value)?;
+            let (day_ids, timestamps) = split_value(&value)?;
 
-            // If we don't have more than a range we don't need to compact
-            if dates.len() > 2 {
-                dates.sort();
-                dates.dedup();
+            if !is_sorted_chunks(day_ids, DAY_ID_LEN) {
+                let mut new_value = sort_chunks(day_ids, DAY_ID_LEN);
 
-                let compacted_dates = if dates.len() <= 2 {
-                    dates
-                } else {
-                    let mut compacted_dates = Vec::with_capacity(2);
+                if let Some((first, last)) = timestamps {
+                    append_timestamps(&mut new_value, first, last);
+                }
 
-                    if let Some(first) = dates.first() {
-                        compacted_dates.push(*first);
-                    }
+                self.db.put(key, new_value)?;
+                count += 1;
+            }
+        }
 
-                    if let Some(last) = dates.last() {
-                        compacted_dates.push(*last);
-                    }
+        Ok(count)
+    }
+}
+
+struct RangeFilterFactory {
+    merge_policy: MergePolicy,
+    compacting_ranges: Arc<AtomicBool>,
+}
 
-                    compacted_dates
-                };
+impl CompactionFilterFactory for RangeFilterFactory {
+    type Filter = RangeFilter;
 
-                let mut new_value = Vec::with_capacity(2 * compacted_dates.len());
+    fn create(&mut self, _context: CompactionFilterContext) -> Self::Filter {
+        RangeFilter {
+            active: self.merge_policy == MergePolicy::Range
+                || self.compacting_ranges.load(Ordering::SeqCst),
+            buffer: Vec::with_capacity(2 * DAY_ID_LEN + TIMESTAMPS_SUFFIX_LEN),
+        }
+    }
 
-                for date in compacted_dates {
-                    new_value.extend_from_slice(&date_to_day_id(&date)?.to_be_bytes());
+    fn name(&self) -> &CStr {
+        c"range-filter-factory"
+    }
+}
+
+/// Compaction filter that reduces values to their first and last observation dates.
+///
+/// Each compaction gets its own filter, so the buffer is never shared between threads.
+struct RangeFilter {
+    active: bool,
+    buffer: Vec<u8>,
+}
+
+impl CompactionFilter for RangeFilter {
+    fn filter(&mut self, _level: u32, _key: &[u8], value: &[u8]) -> Decision {
+        if !self.active {
+            return Decision::Keep;
+        }
+
+        match split_value(value) {
+            Ok((day_ids, timestamps)) if day_ids.len() > 2 * DAY_ID_LEN => {
+                self.buffer.clear();
+                self.buffer.extend_from_slice(&day_ids[..DAY_ID_LEN]);
+                self.buffer
+                    .extend_from_slice(&day_ids[day_ids.len() - DAY_ID_LEN..]);
+
+                if let Some((first, last)) = timestamps {
+                    append_timestamps(&mut self.buffer, first, last);
                 }
 
-                self.db.put(key, new_value)?;
+                // SAFETY: `Decision::Change` requires a static slice, but RocksDB copies the new
+                // value into its own string as soon as this call returns, and the buffer is not
+                // modified or dropped until the next call.
+                let new_value = unsafe { &*(self.buffer.as_slice() as *const [u8]) };
+
+                Decision::Change(new_value)
+            }
+            Ok(_) => Decision::Keep,
+            Err(error) => {
+                log::error!("{}", error);
+                Decision::Keep
             }
         }
+    }
 
-        Ok(())
+    fn name(&self) -> &CStr {
+        c"range-filter"
     }
 }
 
@@ -249,53 +462,50 @@ fn merge(
     _new_key: &[u8],
     existing_val: Option<&[u8]>,
     operands: &MergeOperands,
+    only_range: bool,
 ) -> Option<Vec<u8>> {
-    let mut new_val = match existing_val {
-        Some(bytes) => bytes.to_vec(),
-        None => Vec::with_capacity(operands.len() * 2),
+    let (mut new_val, mut timestamps) = match existing_val.map(split_value) {
+        Some(Ok((day_ids, timestamps))) => (day_ids.to_vec(), timestamps),
+        Some(Err(error)) => {
+            log::error!("{}", error);
+            return None;
+        }
+        None => (vec![], None),
     };
 
     for operand in operands.iter() {
-        merge_for_pair(&mut new_val, operand);
-    }
+        match split_value(operand) {
+            Ok((day_ids, operand_timestamps)) => {
+                new_val = merge_sorted_chunks(&new_val, day_ids, DAY_ID_LEN);
 
-    Some(new_val)
-}
-
-fn merge_for_pair(a: &mut Vec<u8>, b: &[u8]) {
-    let original_len = a.len();
-    let mut i = 0;
+                if only_range && new_val.len() > 2 * DAY_ID_LEN {
+                    new_val.drain(DAY_ID_LEN..new_val.len() - DAY_ID_LEN);
+                }
 
-    while i < b.len() {
-        let bytes: [u8; 2] = match b[i..i + 2].try_into() {
-            Ok(bytes) => bytes,
+                timestamps = merge_timestamps(timestamps, operand_timestamps);
+            }
             Err(error) => {
                 log::error!("{}", error);
-                return;
             }
-        };
-        let next_b = u16::from_be_bytes(bytes);
+        }
+    }
 
-        let mut found = false;
-        let mut j = 0;
+    if let Some((first, last)) = timestamps {
+        append_timestamps(&mut new_val, first, last);
+    }
 
-        while !found && j < original_len {
-            let bytes = match a[j..j + 2].try_into() {
-                Ok(bytes) => bytes,
-                Err(error) => {
-                    log::error!("{}", error);
-                    return;
-                }
-            };
-            let next_a = u16::from_be_bytes(bytes);
-            found = next_a == next_b;
-            j += 2;
-        }
+    Some(new_val)
+}
 
-        if !found {
-            a.extend_from_slice(&b[i..i + 2]);
+fn merge_timestamps(
+    a: Option<EpochSecondRange>,
+    b: Option<EpochSecondRange>,
+) -> Option<EpochSecondRange> {
+    match (a, b) {
+        (Some((first_a, last_a)), Some((first_b, last_b))) => {
+            Some((first_a.min(first_b), last_a.max(last_b)))
         }
-        i += 2;
+        (a, b) => a.or(b),
     }
 }
 
@@ -304,7 +514,7 @@ pub struct PairIterator<'a> {
 }
 
 impl Iterator for PairIterator<'_> {
-    type Item = Result<(u64, String, Vec<NaiveDate>), Error>;
+    type Item = Result<(u64, String, Observations), Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.underlying.next().map(|result| {
@@ -315,11 +525,11 @@ impl Iterator for PairIterator<'_> {
     }
 }
 
-fn kv_to_item(key: &[u8], value: &[u8]) -> Result<(u64, String, Vec<NaiveDate>), Error> {
+fn kv_to_item(key: &[u8], value: &[u8]) -> Result<(u64, String, Observations), Error> {
     let (id, screen_name) = key_to_pair(key)?;
-    let dates = value_to_dates(value)?;
+    let observations = value_to_observations(value)?;
 
-    Ok((id, screen_name.to_string(), dates))
+    Ok((id, screen_name.to_string(), observations))
 }
 
 fn id_to_key_prefix(id: u64) -> [u8; 8] {
@@ -328,9 +538,9 @@ fn id_to_key_prefix(id: u64) -> [u8; 8] {
 
 fn key_prefix_to_id(key: &[u8]) -> Result<u64, Error> {
     Ok(u64::from_be_bytes(
-        key[0..8]
-            .try_into()
-            .map_err(|_| Error::InvalidKey(key.to_vec()))?,
+        key.get(0..8)
+            .and_then(|prefix| prefix.try_into().ok())
+            .ok_or_else(|| Error::InvalidKey(key.to_vec()))?,
     ))
 }
 
@@ -342,7 +552,7 @@ fn pair_to_key(id: u64, screen_name: &str) -> Vec<u8> {
     prefix
 }
 
-fn key_to_pair(key: &[u8]) -> Result<(u64, &str), Error> {
+pub(crate) fn key_to_pair(key: &[u8]) -> Result<(u64, &str), Error> {
     let id = key_prefix_to_id(key)?;
     let screen_name = std::str::from_utf8(&key[8..])?;
 
@@ -358,17 +568,75 @@ fn date_to_day_id(date: &NaiveDate) -> Result<u16, Error> {
     day.try_into().map_err(|_| Error::InvalidDay(day))
 }
 
+/// Whether a date can be stored (dates are stored as two-byte day offsets from the first tweet).
+pub fn is_storable_date(date: &NaiveDate) -> bool {
+    date_to_day_id(date).is_ok()
+}
+
 fn day_id_to_date(day_id: u16) -> NaiveDate {
     *TWITTER_EPOCH + Duration::days(day_id.into())
 }
 
+/// Marks a value that ends with a precise observation range.
+///
+/// Values are otherwise sorted sequences of two-byte day IDs, so the odd length of the suffix also
+/// distinguishes these values.
+const TIMESTAMPS_TAG: u8 = 1;
+const TIMESTAMPS_SUFFIX_LEN: usize = 9;
+
+/// First and last observation times as epoch seconds.
+type EpochSecondRange = (u32, u32);
+
+fn timestamp_to_epoch_second(timestamp: &DateTime<Utc>) -> Result<u32, Error> {
+    let epoch_second = timestamp.timestamp();
+    epoch_second
+        .try_into()
+        .map_err(|_| Error::InvalidTimestamp(epoch_second))
+}
+
+fn epoch_second_to_timestamp(epoch_second: u32) -> Result<DateTime<Utc>, Error> {
+    Utc.timestamp_opt(epoch_second.into(), 0)
+        .single()
+        .ok_or(Error::InvalidTimestamp(epoch_second.into()))
+}
+
+fn append_timestamps(value: &mut Vec<u8>, first: u32, last: u32) {
+    value.extend_from_slice(&first.to_be_bytes());
+    value.extend_from_slice(&last.to_be_bytes());
+    value.push(TIMESTAMPS_TAG);
+}
+
+/// Split a value into its day IDs and its optional first and last observation epoch seconds.
+fn split_value(value: &[u8]) -> Result<(&[u8], Option<EpochSecondRange>), Error> {
+    if value.len().is_multiple_of(DAY_ID_LEN) {
+        Ok((value, None))
+    } else if value.len() >= TIMESTAMPS_SUFFIX_LEN && value[value.len() - 1] == TIMESTAMPS_TAG {
+        let (day_ids, suffix) = value.split_at(value.len() - TIMESTAMPS_SUFFIX_LEN);
+        let first = u32::from_be_bytes(
+            suffix[0..4]
+                .try_into()
+                .map_err(|_| Error::InvalidValue(value.to_vec()))?,
+        );
+        let last = u32::from_be_bytes(
+            suffix[4..8]
+                .try_into()
+                .map_err(|_| Error::InvalidValue(value.to_vec()))?,
+        );
+
+        Ok((day_ids, Some((first, last))))
+    } else {
+        Err(Error::InvalidValue(value.to_vec()))
+    }
+}
+
 fn value_to_dates(value: &[u8]) -> Result<Vec<NaiveDate>, Error> {
-    let count = value.len() / 2;
+    let (day_ids, _) = split_value(value)?;
+    let count = day_ids.len() / 2;
     let mut result = Vec::with_capacity(count);
 
     for i in 0..count {
         let day_id = u16::from_be_bytes(
-            value[i * 2..(i * 2 + 2)]
+            day_ids[i * 2..(i * 2 + 2)]
                 .try_into()
                 .map_err(|_| Error::InvalidValue(value.to_vec()))?,
         );
@@ -378,3 +646,42 @@ fn value_to_dates(value: &[u8]) -> Result<Vec<NaiveDate>, Error> {
     result.sort();
     Ok(result)
 }
+
+pub(crate) fn value_to_observations(value: &[u8]) -> Result<Observations, Error> {
+    let dates = value_to_dates(value)?;
+    let timestamps = match split_value(value)?.1 {
+        Some((first, last)) => Some((
+            epoch_second_to_timestamp(first)?,
+            epoch_second_to_timestamp(last)?,
+        )),
+        None => None,
+    };
+
+    Ok(Observations { dates, timestamps })
+}
+
+fn observations_to_value(observations: &Observations) -> Result<Vec<u8>, Error> {
+    let mut day_ids = observations
+        .dates
+        .iter()
+        .map(date_to_day_id)
+        .collect::<Result<Vec<_>, _>>()?;
+    day_ids.sort();
+    day_ids.dedup();
+
+    let mut value = Vec::with_capacity(DAY_ID_LEN * day_ids.len() + TIMESTAMPS_SUFFIX_LEN);
+
+    for day_id in day_ids {
+        value.extend_from_slice(&day_id.to_be_bytes());
+    }
+
+    if let Some((first, last)) = &observations.timestamps {
+        append_timestamps(
+            &mut value,
+            timestamp_to_epoch_second(first)?,
+            timestamp_to_epoch_second(last)?,
+        );
+    }
+
+    Ok(value)
+}
@@ -0,0 +1,281 @@
+//! Detection of impossible or suspicious records in the account table.
+use super::{
+    accounts::{self, Observations},
+    table::{Table, Writeable},
+    util::is_valid_screen_name,
+    Database, Error,
+};
+use crate::snowflake;
+use chrono::{DateTime, NaiveDate, Utc};
+use rocksdb::{DBIterator, IteratorMode};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+
+/// A problem with a record (or, for case duplicates, a group of records) in the account table.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Anomaly {
+    /// A key that cannot be decoded as a user ID and screen name.
+    InvalidKey {
+        key: Vec<u8>,
+    },
+    /// A value that cannot be decoded as observations.
+    InvalidValue {
+        id: u64,
+        screen_name: String,
+    },
+    InvalidScreenName {
+        id: u64,
+        screen_name: String,
+    },
+    /// An observation before the creation time encoded in a snowflake ID.
+    ///
+    /// Estimated creation times for sequential IDs are not precise enough to be checked.
+    BeforeCreation {
+        id: u64,
+        screen_name: String,
+        first_seen: NaiveDate,
+        created: DateTime<Utc>,
+    },
+    /// An observation after the date of the audit.
+    FutureDate {
+        id: u64,
+        screen_name: String,
+        last_seen: NaiveDate,
+    },
+    /// A pair with neither observation dates nor timestamps.
+    EmptyDates {
+        id: u64,
+        screen_name: String,
+    },
+    /// Screen names for the same account that differ only in case.
+    CaseDuplicate {
+        id: u64,
+        screen_names: Vec<String>,
+    },
+}
+
+impl Anomaly {
+    /// Whether the record is removed when fixing (empty dates and case duplicates are legitimate
+    /// in some data sets, so these are only reported).
+    pub fn is_fixable(&self) -> bool {
+        !matches!(self, Self::EmptyDates { .. } | Self::CaseDuplicate { .. })
+    }
+}
+
+/// A raw account table key and value.
+pub type Record = (Box<[u8]>, Box<[u8]>);
+
+/// An anomaly together with the raw record it was found in (if it applies to a single record).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Finding {
+    pub anomaly: Anomaly,
+    pub record: Option<Record>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct AuditCounts {
+    pub invalid_keys: u64,
+    pub invalid_values: u64,
+    pub invalid_screen_names: u64,
+    pub before_creation: u64,
+    pub future_dates: u64,
+    pub empty_dates: u64,
+    pub case_duplicates: u64,
+}
+
+impl AuditCounts {
+    pub fn update(&mut self, anomaly: &Anomaly) {
+        match anomaly {
+            Anomaly::InvalidKey { .. } => self.invalid_keys += 1,
+            Anomaly::InvalidValue { .. } => self.invalid_values += 1,
+            Anomaly::InvalidScreenName { .. } => self.invalid_screen_names += 1,
+            Anomaly::BeforeCreation { .. } => self.before_creation += 1,
+            Anomaly::FutureDate { .. } => self.future_dates += 1,
+            Anomaly::EmptyDates { .. } => self.empty_dates += 1,
+            Anomaly::CaseDuplicate { .. } => self.case_duplicates += 1,
+        }
+    }
+}
+
+/// Walks the account table in key order, producing every anomaly found.
+pub struct Audit<'a> {
+    underlying: DBIterator<'a>,
+    today: NaiveDate,
+    current_id: Option<u64>,
+    /// Screen names for the current account, grouped by lowercase form.
+    current_screen_names: HashMap<String, Vec<String>>,
+    pending: VecDeque<Finding>,
+}
+
+impl<'a> Audit<'a> {
+    pub(super) fn new(underlying: DBIterator<'a>, today: NaiveDate) -> Self {
+        Self {
+            underlying,
+            today,
+            current_id: None,
+            current_screen_names: HashMap::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn finish_account(&mut self) {
+        let mut groups = std::mem::take(&mut self.current_screen_names)
+            .into_values()
+            .filter(|screen_names| screen_names.len() > 1)
+            .collect::<Vec<_>>();
+        groups.sort();
+
+        if let Some(id) = self.current_id {
+            for screen_names in groups {
+                self.pending.push_back(Finding {
+                    anomaly: Anomaly::CaseDuplicate { id, screen_names },
+                    record: None,
+                });
+            }
+        }
+    }
+
+    fn check(&mut self, key: Box<[u8]>, value: Box<[u8]>) {
+        let (id, screen_name) = match accounts::key_to_pair(&key) {
+            Ok((id, screen_name)) => (id, screen_name.to_string()),
+            Err(_) => {
+                self.pending.push_back(Finding {
+                    anomaly: Anomaly::InvalidKey { key: key.to_vec() },
+                    record: Some((key, value)),
+                });
+                return;
+            }
+        };
+
+        if self.current_id != Some(id) {
+            self.finish_account();
+            self.current_id = Some(id);
+        }
+
+        self.current_screen_names
+            .entry(screen_name.to_lowercase())
+            .or_default()
+            .push(screen_name.clone());
+
+        let anomaly = if !is_valid_screen_name(&screen_name) {
+            Some(Anomaly::InvalidScreenName { id, screen_name })
+        } else {
+            match accounts::value_to_observations(&value) {
+                Ok(observations) => check_observations(id, screen_name, &observations, self.today),
+                Err(_) => Some(Anomaly::InvalidValue { id, screen_name }),
+            }
+        };
+
+        if let Some(anomaly) = anomaly {
+            self.pending.push_back(Finding {
+                anomaly,
+                record: Some((key, value)),
+            });
+        }
+    }
+}
+
+fn check_observations(
+    id: u64,
+    screen_name: String,
+    observations: &Observations,
+    today: NaiveDate,
+) -> Option<Anomaly> {
+    let first_seen = observations
+        .dates
+        .first()
+        .copied()
+        .into_iter()
+        .chain(observations.first_seen().map(|first| first.date_naive()))
+        .min();
+    let last_seen = observations
+        .dates
+        .last()
+        .copied()
+        .into_iter()
+        .chain(observations.last_seen().map(|last| last.date_naive()))
+        .max();
+
+    match (first_seen, last_seen) {
+        (Some(first_seen), Some(last_seen)) => {
+            let created = snowflake::snowflake_to_date_time(id)
+                .filter(|created| first_seen < created.date_naive());
+
+            if let Some(created) = created {
+                Some(Anomaly::BeforeCreation {
+                    id,
+                    screen_name,
+                    first_seen,
+                    created,
+                })
+            } else if last_seen > today {
+                Some(Anomaly::FutureDate {
+                    id,
+                    screen_name,
+                    last_seen,
+                })
+            } else {
+                None
+            }
+        }
+        _ => Some(Anomaly::EmptyDates { id, screen_name }),
+    }
+}
+
+impl Iterator for Audit<'_> {
+    type Item = Result<Finding, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(finding) = self.pending.pop_front() {
+                return Some(Ok(finding));
+            }
+
+            match self.underlying.next() {
+                Some(Ok((key, value))) => self.check(key, value),
+                Some(Err(error)) => return Some(Err(error.into())),
+                None => {
+                    self.finish_account();
+                    self.current_id = None;
+
+                    if self.pending.is_empty() {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<M> Database<M> {
+    /// Scan the account table for anomalies, treating observations after `today` as errors.
+    pub fn audit(&self, today: NaiveDate) -> Audit<'_> {
+        Audit::new(self.accounts.ordered_iterator(IteratorMode::Start), today)
+    }
+}
+
+impl Database<Writeable> {
+    /// Remove the record for a fixable finding, copying it unchanged to the quarantine database
+    /// first if one is provided, and returning whether anything was removed.
+    ///
+    /// The screen name index is not updated, and should be rebuilt after fixing.
+    pub fn remove_finding(
+        &self,
+        finding: &Finding,
+        quarantine: Option<&Database<Writeable>>,
+    ) -> Result<bool, Error> {
+        match &finding.record {
+            Some((key, value)) if finding.anomaly.is_fixable() => {
+                if let Some(quarantine) = quarantine {
+                    quarantine.accounts.underlying().put(key, value)?;
+                }
+
+                self.accounts.underlying().delete(key)?;
+
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! An index from confusable "skeletons" to the screen names that collapse to them.
+//!
+//! Skeletons are computed by lowercasing, removing underscores and trailing digits, and mapping
+//! characters that are commonly swapped by impersonators (such as `0` and `o`, or `I` and `l`) to a
+//! single representative. Keys are the skeleton followed by a zero byte and the lowercased screen
+//! name, and values are empty.
+use super::{
+    screen_names::ScreenNameTable,
+    table::{Mode, Table, Writeable},
+    Error,
+};
+use rocksdb::{IteratorMode, Options, DB};
+use std::marker::PhantomData;
+use std::path::Path;
+
+const SEPARATOR: u8 = 0;
+
+pub struct ConfusableTable<M> {
+    db: Option<DB>,
+    options: Options,
+    mode: PhantomData<M>,
+}
+
+impl<M> Table for ConfusableTable<M> {
+    type Counts = u64;
+
+    fn underlying(&self) -> &DB {
+        self.db.as_ref().unwrap()
+    }
+
+    /// The number of indexed screen names.
+    fn get_counts(&self) -> Result<Self::Counts, Error> {
+        let mut count = 0;
+
+        for result in self.underlying().iterator(IteratorMode::Start) {
+            result?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+}
+
+impl<M> ConfusableTable<M> {
+    /// All indexed (lowercased) screen names with the same skeleton as the given screen name.
+    pub fn lookup(&self, screen_name: &str) -> Result<Vec<String>, Error> {
+        let mut prefix = skeleton(screen_name).into_bytes();
+        prefix.push(SEPARATOR);
+
+        let iter = self.underlying().prefix_iterator(&prefix);
+        let mut results = vec![];
+
+        for result in iter {
+            let (key, _) = result?;
+
+            if key.starts_with(&prefix) {
+                results.push(std::str::from_utf8(&key[prefix.len()..])?.to_string());
+            } else {
+                break;
+            }
+        }
+
+        Ok(results)
+    }
+}
+
+impl<M: Mode> ConfusableTable<M> {
+    pub fn open<P: AsRef<Path>>(path: P, options: Options) -> Result<Self, Error> {
+        let db = if M::is_read_only() {
+            DB::open_for_read_only(&options, path, true)?
+        } else {
+            DB::open(&options, path)?
+        };
+
+        Ok(Self {
+            db: Some(db),
+            options,
+            mode: PhantomData,
+        })
+    }
+}
+
+impl ConfusableTable<Writeable> {
+    pub fn insert(&self, screen_name: &str) -> Result<(), Error> {
+        Ok(self.underlying().put(screen_name_to_key(screen_name), [])?)
+    }
+
+    pub fn remove(&self, screen_name: &str) -> Result<(), Error> {
+        Ok(self.underlying().delete(screen_name_to_key(screen_name))?)
+    }
+
+    /// Index every screen name in the screen name index, returning the number indexed.
+    pub(crate) fn insert_all<N>(&self, screen_names: &ScreenNameTable<N>) -> Result<usize, Error> {
+        let mut count = 0;
+
+        for result in screen_names.underlying().iterator(IteratorMode::Start) {
+            let (key, _) = result?;
+            self.insert(std::str::from_utf8(&key)?)?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    pub fn rebuild<N>(&mut self, screen_names: &ScreenNameTable<N>) -> Result<(), Error> {
+        let path = self.db.as_ref().unwrap().path().to_path_buf();
+        self.db.take().unwrap();
+
+        DB::destroy(&self.options, &path)?;
+
+        self.db = Some(DB::open(&self.options, &path)?);
+        self.insert_all(screen_names)?;
+
+        Ok(())
+    }
+}
+
+pub(crate) fn screen_name_to_key(screen_name: &str) -> Vec<u8> {
+    let mut key = skeleton(screen_name).into_bytes();
+    key.push(SEPARATOR);
+    key.extend_from_slice(screen_name.to_lowercase().as_bytes());
+    key
+}
+
+/// The confusable skeleton of a screen name.
+///
+/// Trailing digits are not removed if the screen name has no other characters.
+pub fn skeleton(screen_name: &str) -> String {
+    let lowercase = screen_name.to_lowercase().replace('_', "");
+    let trimmed = lowercase.trim_end_matches(|ch: char| ch.is_ascii_digit());
+    let base = if trimmed.is_empty() {
+        &lowercase
+    } else {
+        trimmed
+    };
+
+    base.chars()
+        .map(|ch| match ch {
+            '0' => 'o',
+            '1' | 'i' | '|' => 'l',
+            '2' => 'z',
+            '3' => 'e',
+            '4' => 'a',
+            '5' => 's',
+            '6' | '8' => 'b',
+            '7' => 't',
+            '9' => 'g',
+            other => other,
+        })
+        .collect::<String>()
+        .replace("rn", "m")
+        .replace("vv", "w")
+}
@@ -0,0 +1,245 @@
+//! Comparison of the account tables of two databases.
+use super::{
+    accounts::{Observations, PairIterator},
+    Error,
+};
+use chrono::NaiveDate;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::iter::Peekable;
+
+/// A difference between an old and a new version of a database.
+///
+/// Account events are produced before the pair events for the same account.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Change {
+    /// An account that only appears in the new database.
+    NewAccount { id: u64 },
+    /// An account that only appears in the old database.
+    RemovedAccount { id: u64 },
+    NewPair {
+        id: u64,
+        screen_name: String,
+        first_seen: Option<NaiveDate>,
+        last_seen: Option<NaiveDate>,
+    },
+    RemovedPair {
+        id: u64,
+        screen_name: String,
+        first_seen: Option<NaiveDate>,
+        last_seen: Option<NaiveDate>,
+    },
+    /// A pair whose first observation date is earlier or last observation date is later.
+    ExtendedRange {
+        id: u64,
+        screen_name: String,
+        old_first_seen: Option<NaiveDate>,
+        old_last_seen: Option<NaiveDate>,
+        new_first_seen: Option<NaiveDate>,
+        new_last_seen: Option<NaiveDate>,
+    },
+    /// A pair whose observations have changed without extending the range (for example because
+    /// of new dates inside the range or new exact observation times).
+    ChangedObservations { id: u64, screen_name: String },
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct DiffCounts {
+    pub new_accounts: u64,
+    pub removed_accounts: u64,
+    pub new_pairs: u64,
+    pub removed_pairs: u64,
+    pub extended_ranges: u64,
+    pub changed_observations: u64,
+}
+
+impl DiffCounts {
+    pub fn update(&mut self, change: &Change) {
+        match change {
+            Change::NewAccount { .. } => self.new_accounts += 1,
+            Change::RemovedAccount { .. } => self.removed_accounts += 1,
+            Change::NewPair { .. } => self.new_pairs += 1,
+            Change::RemovedPair { .. } => self.removed_pairs += 1,
+            Change::ExtendedRange { .. } => self.extended_ranges += 1,
+            Change::ChangedObservations { .. } => self.changed_observations += 1,
+        }
+    }
+}
+
+type Pair = (u64, String, Observations);
+
+/// Walks two account tables in key order, producing the changes from the old to the new table.
+pub struct Diff<'a, 'b> {
+    old: Peekable<PairIterator<'a>>,
+    new: Peekable<PairIterator<'b>>,
+    last_old_id: Option<u64>,
+    last_new_id: Option<u64>,
+    pending: VecDeque<Change>,
+}
+
+impl<'a, 'b> Diff<'a, 'b> {
+    pub(super) fn new(old: PairIterator<'a>, new: PairIterator<'b>) -> Self {
+        Self {
+            old: old.peekable(),
+            new: new.peekable(),
+            last_old_id: None,
+            last_new_id: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn next_pair(
+        iter: &mut Peekable<PairIterator<'_>>,
+        last_id: &mut Option<u64>,
+    ) -> Result<Option<Pair>, Error> {
+        let pair = iter.next().transpose()?;
+
+        if let Some((id, _, _)) = &pair {
+            *last_id = Some(*id);
+        }
+
+        Ok(pair)
+    }
+
+    /// Whether the given table contains the account (given that every pair with a smaller key
+    /// has already been consumed).
+    fn has_account(
+        iter: &mut Peekable<PairIterator<'_>>,
+        last_id: Option<u64>,
+        id: u64,
+    ) -> Result<bool, Error> {
+        if last_id == Some(id) {
+            Ok(true)
+        } else {
+            match iter.peek() {
+                Some(Ok((next_id, _, _))) => Ok(*next_id == id),
+                Some(Err(_)) => Err(iter.next().unwrap().unwrap_err()),
+                None => Ok(false),
+            }
+        }
+    }
+
+    fn step(&mut self) -> Result<bool, Error> {
+        let ordering = match (self.old.peek(), self.new.peek()) {
+            (None, None) => return Ok(false),
+            (Some(Err(_)), _) => return Err(self.old.next().unwrap().unwrap_err()),
+            (_, Some(Err(_))) => return Err(self.new.next().unwrap().unwrap_err()),
+            (Some(Ok(_)), None) => Ordering::Less,
+            (None, Some(Ok(_))) => Ordering::Greater,
+            (Some(Ok((old_id, old_screen_name, _))), Some(Ok((new_id, new_screen_name, _)))) => {
+                (old_id, old_screen_name).cmp(&(new_id, new_screen_name))
+            }
+        };
+
+        match ordering {
+            Ordering::Less => {
+                let previous_id = self.last_old_id;
+
+                if let Some((id, screen_name, observations)) =
+                    Self::next_pair(&mut self.old, &mut self.last_old_id)?
+                {
+                    if previous_id != Some(id)
+                        && !Self::has_account(&mut self.new, self.last_new_id, id)?
+                    {
+                        self.pending.push_back(Change::RemovedAccount { id });
+                    }
+
+                    self.pending.push_back(Change::RemovedPair {
+                        id,
+                        screen_name,
+                        first_seen: observations.dates.first().copied(),
+                        last_seen: observations.dates.last().copied(),
+                    });
+                }
+            }
+            Ordering::Greater => {
+                let previous_id = self.last_new_id;
+
+                if let Some((id, screen_name, observations)) =
+                    Self::next_pair(&mut self.new, &mut self.last_new_id)?
+                {
+                    if previous_id != Some(id)
+                        && !Self::has_account(&mut self.old, self.last_old_id, id)?
+                    {
+                        self.pending.push_back(Change::NewAccount { id });
+                    }
+
+                    self.pending.push_back(Change::NewPair {
+                        id,
+                        screen_name,
+                        first_seen: observations.dates.first().copied(),
+                        last_seen: observations.dates.last().copied(),
+                    });
+                }
+            }
+            Ordering::Equal => {
+                let old = Self::next_pair(&mut self.old, &mut self.last_old_id)?;
+                let new = Self::next_pair(&mut self.new, &mut self.last_new_id)?;
+
+                if let (Some((id, screen_name, old)), Some((_, _, new))) = (old, new) {
+                    if let Some(change) = compare(id, screen_name, &old, &new) {
+                        self.pending.push_back(change);
+                    }
+                }
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+fn compare(id: u64, screen_name: String, old: &Observations, new: &Observations) -> Option<Change> {
+    if old == new {
+        return None;
+    }
+
+    let old_first_seen = old.dates.first().copied();
+    let old_last_seen = old.dates.last().copied();
+    let new_first_seen = new.dates.first().copied();
+    let new_last_seen = new.dates.last().copied();
+
+    let extends_start = match (old_first_seen, new_first_seen) {
+        (Some(old), Some(new)) => new < old,
+        (None, Some(_)) => true,
+        _ => false,
+    };
+
+    let extends_end = match (old_last_seen, new_last_seen) {
+        (Some(old), Some(new)) => new > old,
+        (None, Some(_)) => true,
+        _ => false,
+    };
+
+    if extends_start || extends_end {
+        Some(Change::ExtendedRange {
+            id,
+            screen_name,
+            old_first_seen,
+            old_last_seen,
+            new_first_seen,
+            new_last_seen,
+        })
+    } else {
+        Some(Change::ChangedObservations { id, screen_name })
+    }
+}
+
+impl Iterator for Diff<'_, '_> {
+    type Item = Result<Change, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(change) = self.pending.pop_front() {
+                return Some(Ok(change));
+            }
+
+            match self.step() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(error) => return Some(Err(error)),
+            }
+        }
+    }
+}
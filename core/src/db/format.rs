@@ -0,0 +1,84 @@
+//! On-disk format versioning and migrations.
+//!
+//! Format version 1 (the original format, assumed for databases without a version record):
+//!
+//! * Account table keys are an 8-byte big-endian user ID followed by the UTF-8 screen name.
+//! * Account table values are concatenated 2-byte big-endian day IDs (counted from
+//!   `TWITTER_EPOCH`, the date of the first tweet).
+//! * Screen name index keys are lowercased screen names, and values are concatenated 8-byte
+//!   big-endian user IDs.
+//!
+//! Format version 2 allows account table values to end with a nine-byte suffix containing the
+//! first and last observation times (as 4-byte big-endian epoch seconds) followed by a tag byte.
+//!
+//! Format version 3 requires the day IDs in account table values and the user IDs in screen name
+//! index values to be sorted and unique, which allows the merge operators to use linear merges.
+//!
+//! Format version 4 adds the confusable index, whose keys are confusable skeletons followed by a
+//! zero byte and a lowercased screen name (see the `confusables` module).
+//!
+//! Any change to these encodings must increment [`FORMAT_VERSION`] and add a [`Migration`] from
+//! the previous version.
+use super::{table::Writeable, Database, Error};
+
+/// The format version written by this version of the library.
+pub const FORMAT_VERSION: u32 = 4;
+
+/// The format version of databases that were created before versions were recorded.
+pub const LEGACY_FORMAT_VERSION: u32 = 1;
+
+/// A step that rewrites a database from one format version to the next.
+pub struct Migration {
+    pub from: u32,
+    pub description: &'static str,
+    run: fn(&Database<Writeable>) -> Result<(), Error>,
+}
+
+impl Migration {
+    pub fn to(&self) -> u32 {
+        self.from + 1
+    }
+
+    pub(crate) fn run(&self, db: &Database<Writeable>) -> Result<(), Error> {
+        (self.run)(db)
+    }
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 1,
+        description: "Allow exact observation timestamps in account values",
+        // Version 1 values are valid version 2 values.
+        run: |_| Ok(()),
+    },
+    Migration {
+        from: 2,
+        description: "Sort and deduplicate account and screen name index values",
+        run: |db| {
+            let account_count = db.accounts.sort_values()?;
+            let screen_name_count = db.screen_names.sort_values()?;
+            log::info!(
+                "Sorted {} account values and {} screen name index values",
+                account_count,
+                screen_name_count
+            );
+            Ok(())
+        },
+    },
+    Migration {
+        from: 3,
+        description: "Build the confusable screen name index",
+        run: |db| {
+            let count = db.confusables.insert_all(&db.screen_names)?;
+            log::info!("Indexed {} screen names by confusable skeleton", count);
+            Ok(())
+        },
+    },
+];
+
+/// Find the migration that upgrades the given format version, if one exists.
+pub fn migration_from(version: u32) -> Option<&'static Migration> {
+    MIGRATIONS
+        .iter()
+        .find(|migration| migration.from == version)
+}
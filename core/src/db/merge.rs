@@ -0,0 +1,129 @@
+//! Merging the contents of one database into another.
+use super::{
+    accounts::{self, Observations},
+    confusables, screen_names,
+    table::{Table, Writeable},
+    Database, Error,
+};
+use chrono::NaiveDate;
+use rocksdb::{Direction, IteratorMode, WriteBatch};
+
+/// Selects the pairs that are merged from a source database.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PairFilter {
+    pub min_id: Option<u64>,
+    pub max_id: Option<u64>,
+    /// Only include pairs with at least one observation date on or after this date.
+    pub earliest: Option<NaiveDate>,
+    /// Only include pairs with at least one observation date on or before this date.
+    pub latest: Option<NaiveDate>,
+}
+
+impl PairFilter {
+    fn has_date_range(&self) -> bool {
+        self.earliest.is_some() || self.latest.is_some()
+    }
+
+    fn includes_id(&self, id: u64) -> bool {
+        self.min_id.filter(|min_id| id < *min_id).is_none()
+            && self.max_id.filter(|max_id| id > *max_id).is_none()
+    }
+
+    /// Pairs without observation dates are excluded if a date range is given.
+    fn includes_observations(&self, observations: &Observations) -> bool {
+        !self.has_date_range()
+            || observations.dates.iter().any(|date| {
+                self.earliest.filter(|earliest| date < earliest).is_none()
+                    && self.latest.filter(|latest| date > latest).is_none()
+            })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct MergeCounts {
+    pub merged_pairs: u64,
+    pub skipped_pairs: u64,
+}
+
+impl Database<Writeable> {
+    /// Merge the selected pairs from another database into this one using the merge operators,
+    /// writing batches of the given size to every table.
+    pub fn merge_from<N>(
+        &self,
+        source: &Database<N>,
+        filter: &PairFilter,
+        batch_size: usize,
+    ) -> Result<MergeCounts, Error> {
+        let start = filter.min_id.unwrap_or(0).to_be_bytes();
+        let iter = source
+            .accounts
+            .ordered_iterator(IteratorMode::From(&start, Direction::Forward));
+
+        let mut counts = MergeCounts::default();
+        let mut account_batch = WriteBatch::default();
+        let mut screen_name_batch = WriteBatch::default();
+        let mut confusable_batch = WriteBatch::default();
+
+        for result in iter {
+            let (key, value) = result?;
+            let (id, screen_name) = accounts::key_to_pair(&key)?;
+
+            if filter.max_id.filter(|max_id| id > *max_id).is_some() {
+                break;
+            }
+
+            if !filter.includes_id(id)
+                || (filter.has_date_range()
+                    && !filter.includes_observations(&accounts::value_to_observations(&value)?))
+            {
+                counts.skipped_pairs += 1;
+                continue;
+            }
+
+            account_batch.merge(&key, &value);
+            screen_name_batch.merge(
+                screen_names::screen_name_to_key(screen_name),
+                id.to_be_bytes(),
+            );
+            confusable_batch.put(confusables::screen_name_to_key(screen_name), []);
+            counts.merged_pairs += 1;
+
+            if account_batch.len() >= batch_size {
+                self.write_batches(
+                    &mut account_batch,
+                    &mut screen_name_batch,
+                    &mut confusable_batch,
+                )?;
+            }
+        }
+
+        self.write_batches(
+            &mut account_batch,
+            &mut screen_name_batch,
+            &mut confusable_batch,
+        )?;
+
+        Ok(counts)
+    }
+
+    fn write_batches(
+        &self,
+        account_batch: &mut WriteBatch,
+        screen_name_batch: &mut WriteBatch,
+        confusable_batch: &mut WriteBatch,
+    ) -> Result<(), Error> {
+        if !account_batch.is_empty() {
+            self.accounts
+                .underlying()
+                .write(std::mem::take(account_batch))?;
+            self.screen_names
+                .underlying()
+                .write(std::mem::take(screen_name_batch))?;
+            self.confusables
+                .underlying()
+                .write(std::mem::take(confusable_batch))?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,433 @@
+use super::{
+    accounts::MergePolicy,
+    table::{Mode, Writeable},
+    util::{merge_sorted_chunks, sort_chunks},
+    Error,
+};
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use rocksdb::{MergeOperands, Options, DB};
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::marker::PhantomData;
+use std::path::Path;
+
+const FORMAT_VERSION_KEY: &[u8] = b"format-version";
+const MERGE_POLICY_KEY: &[u8] = b"merge-policy";
+const IMPORT_KEY_PREFIX: &[u8] = b"import:";
+const IMPORT_BATCH_SEQUENCE_KEY: &[u8] = b"import-batch-sequence";
+const IMPORT_BATCH_KEY_PREFIX: &[u8] = b"import-batch:";
+const IMPORT_BATCH_PAIR_KEY_PREFIX: &[u8] = b"import-batch-pair:";
+const IMPORT_BATCH_DISPLACED_KEY_PREFIX: &[u8] = b"import-batch-displaced:";
+const DAY_LEN: usize = 4;
+
+/// A ledger entry for an imported input (such as a Stream Grab directory).
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ImportRecord {
+    pub path: String,
+    pub files: Vec<ImportedFile>,
+    /// The number of pair observations written (pairs written in several chunks are counted once
+    /// per chunk).
+    pub entries: u64,
+    pub imported_at: DateTime<Utc>,
+    /// The import batch that wrote the observations (missing for entries recorded before import
+    /// batches were introduced).
+    #[serde(default)]
+    pub batch: Option<u64>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ImportedFile {
+    pub name: String,
+    /// Hex-encoded SHA-256 hash of the file contents.
+    pub sha256: String,
+    pub entries: u64,
+    /// Size in bytes when imported (missing for entries recorded before sizes were tracked).
+    #[serde(default)]
+    pub size: Option<u64>,
+    /// Modification time when imported (missing for entries recorded before it was tracked).
+    #[serde(default)]
+    pub modified: Option<DateTime<Utc>>,
+}
+
+impl ImportRecord {
+    /// Whether the record covers exactly the given files (by name and hash).
+    pub fn has_same_files(&self, files: &[ImportedFile]) -> bool {
+        self.files.len() == files.len()
+            && self
+                .files
+                .iter()
+                .zip(files)
+                .all(|(a, b)| a.name == b.name && a.sha256 == b.sha256)
+    }
+
+    /// Whether the record covers the given files by name, size, and modification time, which lets
+    /// unchanged files be recognized without hashing them.
+    pub fn has_same_file_metadata(&self, files: &[ImportedFile]) -> bool {
+        self.files.len() == files.len()
+            && self.files.iter().zip(files).all(|(a, b)| {
+                a.name == b.name
+                    && a.size.is_some()
+                    && a.size == b.size
+                    && a.modified.is_some()
+                    && a.modified == b.modified
+            })
+    }
+}
+
+/// An audit log entry for a single import run.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ImportBatch {
+    pub id: u64,
+    /// The import command (for example `import-mentions`).
+    pub command: String,
+    pub operator: Option<String>,
+    pub inputs: Vec<BatchInput>,
+    pub started_at: DateTime<Utc>,
+    /// Missing if the import failed or is still running.
+    pub finished_at: Option<DateTime<Utc>>,
+    /// The number of pair observations written (pairs written in several chunks are counted once
+    /// per chunk).
+    pub entries: u64,
+    pub rejected: u64,
+    /// Whether the pairs written by the batch were recorded (which is required for rollback).
+    pub pairs_recorded: bool,
+    pub rolled_back_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BatchInput {
+    pub path: String,
+    /// Hex-encoded SHA-256 hash of the file contents (missing for directories and standard input).
+    pub sha256: Option<String>,
+}
+
+/// Small key-value table for database-level metadata (such as the format version).
+///
+/// Databases created before this table was introduced will not have it, so in read-only mode a
+/// missing table is treated as empty.
+pub struct MetadataTable<M> {
+    db: Option<DB>,
+    mode: PhantomData<M>,
+}
+
+impl<M> MetadataTable<M> {
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        match &self.db {
+            Some(db) => Ok(db.get(key)?),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_format_version(&self) -> Result<Option<u32>, Error> {
+        self.get(FORMAT_VERSION_KEY)?
+            .map(|value| {
+                Ok(u32::from_be_bytes(
+                    value
+                        .as_slice()
+                        .try_into()
+                        .map_err(|_| Error::InvalidValue(value.clone()))?,
+                ))
+            })
+            .transpose()
+    }
+
+    pub fn get_merge_policy(&self) -> Result<MergePolicy, Error> {
+        match self.get(MERGE_POLICY_KEY)? {
+            Some(value) => std::str::from_utf8(&value)?.parse(),
+            None => Ok(MergePolicy::default()),
+        }
+    }
+
+    pub fn get_import_record(&self, path: &str) -> Result<Option<ImportRecord>, Error> {
+        self.get(&import_key(path))?
+            .map(|value| Ok(serde_json::from_slice(&value)?))
+            .transpose()
+    }
+
+    /// All import ledger entries, in path order.
+    pub fn get_import_records(&self) -> Result<Vec<ImportRecord>, Error> {
+        let mut records = vec![];
+
+        if let Some(db) = &self.db {
+            for result in db.prefix_iterator(IMPORT_KEY_PREFIX) {
+                let (key, value) = result?;
+
+                if !key.starts_with(IMPORT_KEY_PREFIX) {
+                    break;
+                }
+
+                records.push(serde_json::from_slice(&value)?);
+            }
+        }
+
+        Ok(records)
+    }
+
+    pub fn get_import_batch(&self, id: u64) -> Result<Option<ImportBatch>, Error> {
+        self.get(&import_batch_key(id))?
+            .map(|value| Ok(serde_json::from_slice(&value)?))
+            .transpose()
+    }
+
+    /// All import batches, in ID order.
+    pub fn get_import_batches(&self) -> Result<Vec<ImportBatch>, Error> {
+        let mut batches = vec![];
+
+        if let Some(db) = &self.db {
+            for result in db.prefix_iterator(IMPORT_BATCH_KEY_PREFIX) {
+                let (key, value) = result?;
+
+                if !key.starts_with(IMPORT_BATCH_KEY_PREFIX) {
+                    break;
+                }
+
+                batches.push(serde_json::from_slice(&value)?);
+            }
+        }
+
+        Ok(batches)
+    }
+
+    /// The dates written by an import batch for a pair (if the batch recorded its pairs).
+    pub fn get_batch_pair(
+        &self,
+        batch: u64,
+        id: u64,
+        screen_name: &str,
+    ) -> Result<Option<Vec<NaiveDate>>, Error> {
+        self.get(&import_batch_pair_key(
+            IMPORT_BATCH_PAIR_KEY_PREFIX,
+            batch,
+            id,
+            screen_name,
+        ))?
+        .map(|value| value_to_dates(&value))
+        .transpose()
+    }
+
+    /// The stored dates that an import batch's observations for a pair may have displaced from
+    /// the pair's range (if any were recorded).
+    pub fn get_batch_displaced_dates(
+        &self,
+        batch: u64,
+        id: u64,
+        screen_name: &str,
+    ) -> Result<Option<Vec<NaiveDate>>, Error> {
+        self.get(&import_batch_pair_key(
+            IMPORT_BATCH_DISPLACED_KEY_PREFIX,
+            batch,
+            id,
+            screen_name,
+        ))?
+        .map(|value| value_to_dates(&value))
+        .transpose()
+    }
+
+    /// All pairs recorded for an import batch, with the dates written for each.
+    pub fn get_batch_pairs(&self, batch: u64) -> Result<Vec<(u64, String, Vec<NaiveDate>)>, Error> {
+        let mut pairs = vec![];
+
+        if let Some(db) = &self.db {
+            let prefix = import_batch_pair_prefix(IMPORT_BATCH_PAIR_KEY_PREFIX, batch);
+
+            for result in db.prefix_iterator(&prefix) {
+                let (key, value) = result?;
+
+                if !key.starts_with(&prefix) {
+                    break;
+                }
+
+                let pair_key = &key[prefix.len()..];
+
+                if pair_key.len() < 8 {
+                    return Err(Error::InvalidKey(key.to_vec()));
+                }
+
+                let id = u64::from_be_bytes(pair_key[0..8].try_into().unwrap());
+                let screen_name = std::str::from_utf8(&pair_key[8..])?.to_string();
+
+                pairs.push((id, screen_name, value_to_dates(&value)?));
+            }
+        }
+
+        Ok(pairs)
+    }
+}
+
+impl<M: Mode> MetadataTable<M> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        // Only used for the dates recorded for import batch pairs (and the dates they displace).
+        options.set_merge_operator_associative("batch-pair-dates", merge_dates);
+
+        let db = if M::is_read_only() {
+            if path.as_ref().exists() {
+                Some(DB::open_for_read_only(&options, path, true)?)
+            } else {
+                None
+            }
+        } else {
+            Some(DB::open(&options, path)?)
+        };
+
+        Ok(Self {
+            db,
+            mode: PhantomData,
+        })
+    }
+
+    /// Record a format version for a database that doesn't have one yet.
+    pub(super) fn init_format_version(&self, version: u32) -> Result<(), Error> {
+        match &self.db {
+            Some(db) if !M::is_read_only() => {
+                Ok(db.put(FORMAT_VERSION_KEY, version.to_be_bytes())?)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl MetadataTable<Writeable> {
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        Ok(self.db.as_ref().unwrap().put(key, value)?)
+    }
+
+    pub fn put_format_version(&self, version: u32) -> Result<(), Error> {
+        self.put(FORMAT_VERSION_KEY, &version.to_be_bytes())
+    }
+
+    /// Set the merge policy for the account table (which takes effect when it is next opened).
+    pub fn put_merge_policy(&self, merge_policy: MergePolicy) -> Result<(), Error> {
+        self.put(MERGE_POLICY_KEY, merge_policy.as_str().as_bytes())
+    }
+
+    pub fn put_import_record(&self, record: &ImportRecord) -> Result<(), Error> {
+        self.put(&import_key(&record.path), &serde_json::to_vec(record)?)
+    }
+
+    pub fn delete_import_record(&self, path: &str) -> Result<(), Error> {
+        Ok(self.db.as_ref().unwrap().delete(import_key(path))?)
+    }
+
+    /// Reserve the next import batch ID (IDs start at 1).
+    pub fn next_import_batch_id(&self) -> Result<u64, Error> {
+        let id = match self.get(IMPORT_BATCH_SEQUENCE_KEY)? {
+            Some(value) => {
+                u64::from_be_bytes(
+                    value
+                        .as_slice()
+                        .try_into()
+                        .map_err(|_| Error::InvalidValue(value.clone()))?,
+                ) + 1
+            }
+            None => 1,
+        };
+
+        self.put(IMPORT_BATCH_SEQUENCE_KEY, &id.to_be_bytes())?;
+
+        Ok(id)
+    }
+
+    pub fn put_import_batch(&self, batch: &ImportBatch) -> Result<(), Error> {
+        self.put(&import_batch_key(batch.id), &serde_json::to_vec(batch)?)
+    }
+
+    /// Record dates written by an import batch for a pair (adding to any dates already recorded).
+    ///
+    /// The dates are combined with the recorded ones by the merge operator, so concurrent writers
+    /// can't lose each other's dates.
+    pub fn put_batch_pair(
+        &self,
+        batch: u64,
+        id: u64,
+        screen_name: &str,
+        dates: &[NaiveDate],
+    ) -> Result<(), Error> {
+        Ok(self.db.as_ref().unwrap().merge(
+            import_batch_pair_key(IMPORT_BATCH_PAIR_KEY_PREFIX, batch, id, screen_name),
+            sort_chunks(&dates_to_value(dates), DAY_LEN),
+        )?)
+    }
+
+    /// Record stored dates that an import batch's observations for a pair may displace (the
+    /// endpoints of the pair's range that the batch extends), so that a rollback can restore them
+    /// after the pair's dates have been reduced to a range.
+    pub fn put_batch_displaced_dates(
+        &self,
+        batch: u64,
+        id: u64,
+        screen_name: &str,
+        dates: &[NaiveDate],
+    ) -> Result<(), Error> {
+        Ok(self.db.as_ref().unwrap().merge(
+            import_batch_pair_key(IMPORT_BATCH_DISPLACED_KEY_PREFIX, batch, id, screen_name),
+            sort_chunks(&dates_to_value(dates), DAY_LEN),
+        )?)
+    }
+}
+
+fn import_batch_key(id: u64) -> Vec<u8> {
+    let mut key = IMPORT_BATCH_KEY_PREFIX.to_vec();
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+fn import_batch_pair_prefix(key_prefix: &[u8], batch: u64) -> Vec<u8> {
+    let mut prefix = key_prefix.to_vec();
+    prefix.extend_from_slice(&batch.to_be_bytes());
+    prefix
+}
+
+fn import_batch_pair_key(key_prefix: &[u8], batch: u64, id: u64, screen_name: &str) -> Vec<u8> {
+    let mut key = import_batch_pair_prefix(key_prefix, batch);
+    key.extend_from_slice(&id.to_be_bytes());
+    key.extend_from_slice(screen_name.as_bytes());
+    key
+}
+
+/// Dates are stored as big-endian day numbers from the common era.
+fn dates_to_value(dates: &[NaiveDate]) -> Vec<u8> {
+    dates
+        .iter()
+        .flat_map(|date| date.num_days_from_ce().to_be_bytes())
+        .collect()
+}
+
+fn value_to_dates(value: &[u8]) -> Result<Vec<NaiveDate>, Error> {
+    if !value.len().is_multiple_of(DAY_LEN) {
+        return Err(Error::InvalidValue(value.to_vec()));
+    }
+
+    value
+        .chunks_exact(DAY_LEN)
+        .map(|bytes| {
+            let day = i32::from_be_bytes(bytes.try_into().unwrap());
+
+            NaiveDate::from_num_days_from_ce_opt(day).ok_or(Error::InvalidDay(day.into()))
+        })
+        .collect()
+}
+
+/// Combine sorted date values (day numbers are positive, so their big-endian encodings sort in
+/// numeric order).
+fn merge_dates(
+    _key: &[u8],
+    existing_val: Option<&[u8]>,
+    operands: &MergeOperands,
+) -> Option<Vec<u8>> {
+    Some(
+        existing_val
+            .into_iter()
+            .chain(operands.iter())
+            .fold(vec![], |dates, value| {
+                merge_sorted_chunks(&dates, value, DAY_LEN)
+            }),
+    )
+}
+
+fn import_key(path: &str) -> Vec<u8> {
+    let mut key = IMPORT_KEY_PREFIX.to_vec();
+    key.extend_from_slice(path.as_bytes());
+    key
+}
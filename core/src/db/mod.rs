@@ -1,11 +1,26 @@
 pub mod accounts;
+pub mod audit;
+pub mod confusables;
+pub mod diff;
+pub mod format;
+pub mod merge;
+pub mod metadata;
+pub mod options;
+pub mod rollback;
 pub mod screen_names;
+pub mod sharded;
 pub mod table;
 pub mod util;
 
-use accounts::AccountTable;
+use accounts::{AccountTable, MergePolicy, Observations};
 use chrono::NaiveDate;
+use confusables::ConfusableTable;
+use format::{Migration, FORMAT_VERSION, LEGACY_FORMAT_VERSION};
+use metadata::MetadataTable;
+pub use options::DatabaseOptions;
+use rocksdb::IteratorMode;
 use screen_names::ScreenNameTable;
+use sharded::MANIFEST_VERSION;
 use std::collections::HashMap;
 use std::path::Path;
 use std::sync::Arc;
@@ -23,8 +38,40 @@ pub enum Error {
     InvalidValue(Vec<u8>),
     #[error("Invalid Twitter epoch day")]
     InvalidDay(i64),
+    #[error("Invalid observation timestamp")]
+    InvalidTimestamp(i64),
     #[error("Invalid Twitter screen name")]
     InvalidScreenName(String),
+    #[error("Invalid merge policy")]
+    InvalidMergePolicy(String),
+    #[error("Unsupported database format version {0} (expected {FORMAT_VERSION})")]
+    UnsupportedFormatVersion(u32),
+    #[error("No migration available from database format version {0}")]
+    MissingMigration(u32),
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid database options")]
+    InvalidOptions(#[from] toml::de::Error),
+    #[error("Invalid shard manifest: {0}")]
+    InvalidManifest(&'static str),
+    #[error("Unsupported shard manifest version {0} (expected {MANIFEST_VERSION})")]
+    UnsupportedManifestVersion(u32),
+    #[error("Not supported for sharded layouts: {0}")]
+    UnsupportedForShardedLayout(&'static str),
+    #[error("Invalid compact file: {0}")]
+    InvalidCompactFile(&'static str),
+    #[error("JSON error")]
+    Json(#[from] serde_json::Error),
+    #[error("Lookup not supported by this backend: {0}")]
+    UnsupportedLookup(&'static str),
+    #[error("Unknown import batch {0}")]
+    UnknownImportBatch(u64),
+    #[error("Import batch {0} did not record its pairs")]
+    MissingBatchPairs(u64),
+    #[error("Import batch {0} has already been rolled back")]
+    BatchRolledBack(u64),
+    #[error("FST error")]
+    Fst(#[from] fst::Error),
     #[error("Channel send error")]
     ChannelSend,
     #[error("Channel receive error")]
@@ -34,6 +81,8 @@ pub enum Error {
 pub struct Database<M> {
     pub accounts: Arc<AccountTable<M>>,
     pub screen_names: ScreenNameTable<M>,
+    pub confusables: ConfusableTable<M>,
+    pub metadata: MetadataTable<M>,
 }
 
 impl<M: Sync + Send + 'static> Database<M> {
@@ -60,10 +109,7 @@ impl<M: Sync + Send + 'static> Database<M> {
         Ok((account_counts, screen_name_counts))
     }
 
-    pub fn lookup_by_user_id(
-        &self,
-        user_id: u64,
-    ) -> Result<HashMap<String, Vec<NaiveDate>>, Error> {
+    pub fn lookup_by_user_id(&self, user_id: u64) -> Result<HashMap<String, Observations>, Error> {
         self.accounts.lookup(user_id)
     }
 
@@ -84,42 +130,191 @@ impl<M: Sync + Send + 'static> Database<M> {
         &self,
         user_id: u64,
         earliest: Option<NaiveDate>,
-    ) -> Result<HashMap<String, Vec<NaiveDate>>, Error> {
+    ) -> Result<HashMap<String, Observations>, Error> {
         match earliest {
             Some(earliest) => self.accounts.limited_lookup(user_id, earliest),
             None => self.accounts.lookup(user_id),
         }
     }
+
+    /// Every screen name (with its accounts) that has the same confusable skeleton as the given
+    /// screen name, in skeleton key order.
+    pub fn lookup_confusables(&self, screen_name: &str) -> Result<Vec<(String, Vec<u64>)>, Error> {
+        let mut results = vec![];
+
+        for screen_name in self.confusables.lookup(screen_name)? {
+            let ids = self.screen_names.lookup(&screen_name)?;
+
+            if !ids.is_empty() {
+                results.push((screen_name, ids));
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// The changes from this database to the other database, in account table key order.
+    pub fn diff<'a, N>(&'a self, other: &'a Database<N>) -> diff::Diff<'a, 'a> {
+        diff::Diff::new(self.accounts.pairs(), other.accounts.pairs())
+    }
 }
 
 impl<M: Mode> Database<M> {
+    /// Open the database, failing if its format version is not supported.
+    ///
+    /// In writeable mode the current format version is recorded if the database doesn't have one.
     pub fn open<P: AsRef<Path>>(base: P) -> Result<Self, Error> {
-        Self::open_from_tables(
+        Self::open_with_options(base, &DatabaseOptions::default())
+    }
+
+    /// Open the database with the given RocksDB tuning options.
+    pub fn open_with_options<P: AsRef<Path>>(
+        base: P,
+        options: &DatabaseOptions,
+    ) -> Result<Self, Error> {
+        let db = Self::open_unchecked(base, options)?;
+        let version = db.get_format_version()?;
+
+        if version == FORMAT_VERSION {
+            Ok(db)
+        } else {
+            Err(Error::UnsupportedFormatVersion(version))
+        }
+    }
+
+    fn open_unchecked<P: AsRef<Path>>(base: P, options: &DatabaseOptions) -> Result<Self, Error> {
+        let db = Self::open_from_tables(
             base.as_ref().join("accounts"),
             base.as_ref().join("screen-names"),
-        )
+            base.as_ref().join("confusables"),
+            base.as_ref().join("metadata"),
+            options,
+        )?;
+
+        if !M::is_read_only() && db.metadata.get_format_version()?.is_none() {
+            let version = if db.is_empty()? {
+                FORMAT_VERSION
+            } else {
+                LEGACY_FORMAT_VERSION
+            };
+
+            db.metadata.init_format_version(version)?;
+        }
+
+        Ok(db)
     }
 
     fn open_from_tables<P: AsRef<Path>>(
         accounts_path: P,
         screen_names_path: P,
+        confusables_path: P,
+        metadata_path: P,
+        options: &DatabaseOptions,
     ) -> Result<Self, Error> {
+        let metadata = MetadataTable::open(metadata_path)?;
+        let merge_policy = metadata.get_merge_policy()?;
+        let cache = options.make_block_cache();
+
         Ok(Self {
-            accounts: Arc::new(AccountTable::open(accounts_path)?),
-            screen_names: ScreenNameTable::open(screen_names_path)?,
+            accounts: Arc::new(AccountTable::open(
+                accounts_path,
+                merge_policy,
+                options.make_account_options(cache.as_ref()),
+            )?),
+            screen_names: ScreenNameTable::open(
+                screen_names_path,
+                options.make_screen_name_options(cache.as_ref()),
+            )?,
+            confusables: ConfusableTable::open(
+                confusables_path,
+                options.make_screen_name_options(cache.as_ref()),
+            )?,
+            metadata,
         })
     }
+
+    pub fn get_merge_policy(&self) -> Result<MergePolicy, Error> {
+        self.metadata.get_merge_policy()
+    }
+
+    /// The format version of the database (databases without a version record are assumed to
+    /// use the legacy format).
+    pub fn get_format_version(&self) -> Result<u32, Error> {
+        Ok(self
+            .metadata
+            .get_format_version()?
+            .unwrap_or(LEGACY_FORMAT_VERSION))
+    }
+
+    fn is_empty(&self) -> Result<bool, Error> {
+        Ok(self
+            .accounts
+            .ordered_iterator(IteratorMode::Start)
+            .next()
+            .transpose()?
+            .is_none())
+    }
 }
 
 impl Database<Writeable> {
     pub fn insert(&self, id: u64, screen_name: &str, dates: Vec<NaiveDate>) -> Result<(), Error> {
         self.accounts.insert(id, screen_name, dates)?;
         self.screen_names.insert(screen_name, id)?;
+        self.confusables.insert(screen_name)?;
         Ok(())
     }
 
+    pub fn insert_observations(
+        &self,
+        id: u64,
+        screen_name: &str,
+        observations: &Observations,
+    ) -> Result<(), Error> {
+        self.accounts
+            .insert_observations(id, screen_name, observations)?;
+        self.screen_names.insert(screen_name, id)?;
+        self.confusables.insert(screen_name)?;
+        Ok(())
+    }
+
+    /// Rebuild the screen name index and the confusable index from the account table.
     pub fn rebuild_index(&mut self) -> Result<(), Error> {
-        self.screen_names.rebuild(&self.accounts)
+        self.screen_names.rebuild(&self.accounts)?;
+        self.confusables.rebuild(&self.screen_names)
+    }
+
+    /// Open the database at the given location and upgrade it to the current format version,
+    /// returning the migrations that were applied.
+    pub fn migrate<P: AsRef<Path>>(
+        base: P,
+        options: &DatabaseOptions,
+    ) -> Result<Vec<&'static Migration>, Error> {
+        let db = Self::open_unchecked(base, options)?;
+        let mut version = db.get_format_version()?;
+        let mut applied = vec![];
+
+        if version > FORMAT_VERSION {
+            return Err(Error::UnsupportedFormatVersion(version));
+        }
+
+        while version < FORMAT_VERSION {
+            let migration =
+                format::migration_from(version).ok_or(Error::MissingMigration(version))?;
+
+            log::info!(
+                "Migrating from format version {} to {}: {}",
+                migration.from,
+                migration.to(),
+                migration.description
+            );
+
+            migration.run(&db)?;
+            version = migration.to();
+            db.metadata.put_format_version(version)?;
+            applied.push(migration);
+        }
+
+        Ok(applied)
     }
 }
 
@@ -138,13 +333,13 @@ mod tests {
         db.insert(123, "foo", vec![]).unwrap();
 
         let mut expected_by_id = HashMap::new();
-        expected_by_id.insert("foo".to_string(), vec![]);
-        expected_by_id.insert("bar".to_string(), vec![]);
+        expected_by_id.insert("foo".to_string(), Observations::default());
+        expected_by_id.insert("bar".to_string(), Observations::default());
 
         let expected_pairs = vec![
-            (123, "bar".to_string(), vec![]),
-            (123, "foo".to_string(), vec![]),
-            (456, "foo".to_string(), vec![]),
+            (123, "bar".to_string(), Observations::default()),
+            (123, "foo".to_string(), Observations::default()),
+            (456, "foo".to_string(), Observations::default()),
         ];
 
         let expected_counts = (
@@ -199,4 +394,926 @@ mod tests {
             expected
         );
     }
+
+    #[test]
+    fn format_version() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let db = Database::<Writeable>::open(&dir).unwrap();
+            db.insert(123, "foo", vec![]).unwrap();
+            assert_eq!(db.get_format_version().unwrap(), FORMAT_VERSION);
+        }
+
+        let db = Database::<ReadOnly>::open(&dir).unwrap();
+        assert_eq!(db.get_format_version().unwrap(), FORMAT_VERSION);
+        drop(db);
+
+        assert!(
+            Database::<Writeable>::migrate(&dir, &DatabaseOptions::default())
+                .unwrap()
+                .is_empty()
+        );
+
+        {
+            let metadata = MetadataTable::<Writeable>::open(dir.path().join("metadata")).unwrap();
+            metadata.put_format_version(LEGACY_FORMAT_VERSION).unwrap();
+        }
+
+        assert!(Database::<ReadOnly>::open(&dir).is_err());
+        assert_eq!(
+            Database::<Writeable>::migrate(&dir, &DatabaseOptions::default())
+                .unwrap()
+                .len() as u32,
+            FORMAT_VERSION - LEGACY_FORMAT_VERSION
+        );
+
+        let db = Database::<ReadOnly>::open(&dir).unwrap();
+        assert_eq!(db.lookup_by_screen_name("foo").unwrap(), vec![123]);
+        drop(db);
+
+        {
+            let metadata = MetadataTable::<Writeable>::open(dir.path().join("metadata")).unwrap();
+            metadata.put_format_version(FORMAT_VERSION + 1).unwrap();
+        }
+
+        assert!(matches!(
+            Database::<ReadOnly>::open(&dir),
+            Err(Error::UnsupportedFormatVersion(version)) if version == FORMAT_VERSION + 1
+        ));
+        assert!(matches!(
+            Database::<Writeable>::migrate(&dir, &DatabaseOptions::default()),
+            Err(Error::UnsupportedFormatVersion(_))
+        ));
+    }
+
+    #[test]
+    fn insert_observations() {
+        use chrono::{TimeZone, Utc};
+
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::open(dir).unwrap();
+
+        let date = NaiveDate::from_ymd_opt(2022, 7, 1).unwrap();
+        let first = Utc.with_ymd_and_hms(2022, 7, 1, 9, 30, 0).unwrap();
+        let middle = Utc.with_ymd_and_hms(2022, 7, 1, 12, 0, 0).unwrap();
+        let last = Utc.with_ymd_and_hms(2022, 7, 1, 18, 15, 5).unwrap();
+
+        db.insert_observations(
+            123,
+            "foo",
+            &Observations::new(vec![date], Some((middle, last))),
+        )
+        .unwrap();
+        db.insert_observations(
+            123,
+            "foo",
+            &Observations::new(vec![date], Some((first, middle))),
+        )
+        .unwrap();
+        db.insert(123, "foo", vec![date.succ_opt().unwrap()])
+            .unwrap();
+
+        let expected = Observations::new(vec![date, date.succ_opt().unwrap()], Some((first, last)));
+
+        assert_eq!(db.lookup_by_user_id(123).unwrap()["foo"], expected);
+    }
+
+    #[test]
+    fn merge_policies() {
+        let dates = |days: &[u32]| {
+            days.iter()
+                .map(|day| NaiveDate::from_ymd_opt(2020, 1, *day).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let db = Database::<Writeable>::open(&dir).unwrap();
+            assert_eq!(db.get_merge_policy().unwrap(), MergePolicy::All);
+
+            db.insert(456, "foo", dates(&[5, 3])).unwrap();
+            db.insert(123, "foo", dates(&[4, 3, 9])).unwrap();
+            db.insert(456, "foo", dates(&[1, 3])).unwrap();
+
+            assert_eq!(db.lookup_by_screen_name("foo").unwrap(), vec![123, 456]);
+            assert_eq!(
+                db.lookup_by_user_id(456).unwrap()["foo"].dates,
+                dates(&[1, 3, 5])
+            );
+
+            db.accounts.compact_ranges().unwrap();
+
+            assert_eq!(
+                db.lookup_by_user_id(456).unwrap()["foo"].dates,
+                dates(&[1, 5])
+            );
+            assert_eq!(
+                db.lookup_by_user_id(123).unwrap()["foo"].dates,
+                dates(&[3, 9])
+            );
+
+            db.insert(123, "foo", dates(&[6])).unwrap();
+            assert_eq!(
+                db.lookup_by_user_id(123).unwrap()["foo"].dates,
+                dates(&[3, 6, 9])
+            );
+
+            // Fold the merge operands into a stored value that only the compaction filter sees.
+            db.accounts
+                .underlying()
+                .compact_range(None::<&[u8]>, None::<&[u8]>);
+            db.accounts.compact_ranges().unwrap();
+
+            assert_eq!(
+                db.lookup_by_user_id(123).unwrap()["foo"].dates,
+                dates(&[3, 9])
+            );
+
+            db.insert(789, "bar", dates(&[1, 2, 3])).unwrap();
+            db.accounts
+                .underlying()
+                .compact_range(None::<&[u8]>, None::<&[u8]>);
+
+            db.metadata.put_merge_policy(MergePolicy::Range).unwrap();
+        }
+
+        let db = Database::<Writeable>::open(&dir).unwrap();
+        assert_eq!(db.get_merge_policy().unwrap(), MergePolicy::Range);
+
+        // Values stored before the policy was set are reduced by the compaction filter.
+        assert_eq!(
+            db.lookup_by_user_id(789).unwrap()["bar"].dates,
+            dates(&[1, 2, 3])
+        );
+        db.accounts
+            .underlying()
+            .compact_range(None::<&[u8]>, None::<&[u8]>);
+        assert_eq!(
+            db.lookup_by_user_id(789).unwrap()["bar"].dates,
+            dates(&[1, 3])
+        );
+
+        db.insert(123, "foo", dates(&[2, 7])).unwrap();
+        db.insert(123, "foo", dates(&[12])).unwrap();
+        assert_eq!(
+            db.lookup_by_user_id(123).unwrap()["foo"].dates,
+            dates(&[2, 12])
+        );
+    }
+
+    #[test]
+    fn options() {
+        let options = DatabaseOptions::from_toml_str(
+            r#"
+            compression = "zstd"
+            zstd_dictionary_bytes = 16384
+            bloom_filter_bits_per_key = 10.0
+            account_id_prefix_extractor = true
+            block_cache_bytes = 1048576
+            max_open_files = 64
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(options.compression, options::Compression::Zstd);
+        assert_eq!(
+            DatabaseOptions::from_toml_str("").unwrap(),
+            DatabaseOptions::default()
+        );
+        assert!(DatabaseOptions::from_toml_str("compression = \"lz4\"").is_err());
+        assert!(DatabaseOptions::from_toml_str("unknown = 1").is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+
+        {
+            let db = Database::<Writeable>::open_with_options(&dir, &options).unwrap();
+            db.insert(123, "foo", vec![]).unwrap();
+            db.insert(123, "bar", vec![]).unwrap();
+            db.insert(456, "foo", vec![]).unwrap();
+            db.accounts
+                .underlying()
+                .compact_range::<&[u8], &[u8]>(None, None);
+        }
+
+        let db = Database::<ReadOnly>::open_with_options(&dir, &options).unwrap();
+        let mut screen_names = db
+            .lookup_by_user_id(123)
+            .unwrap()
+            .into_keys()
+            .collect::<Vec<_>>();
+        screen_names.sort();
+
+        assert_eq!(screen_names, vec!["bar", "foo"]);
+        assert!(db.lookup_by_user_id(789).unwrap().is_empty());
+        assert_eq!(db.lookup_by_screen_name("FOO").unwrap(), vec![123, 456]);
+    }
+
+    #[test]
+    fn diff() {
+        use diff::{Change, DiffCounts};
+
+        let date = |day| NaiveDate::from_ymd_opt(2020, 1, day).unwrap();
+        let old_dir = tempfile::tempdir().unwrap();
+        let new_dir = tempfile::tempdir().unwrap();
+
+        let old = Database::<Writeable>::open(&old_dir).unwrap();
+        old.insert(1, "removed", vec![date(1)]).unwrap();
+        old.insert(2, "foo", vec![date(2), date(4)]).unwrap();
+        old.insert(2, "gone", vec![]).unwrap();
+        old.insert(3, "bar", vec![date(2), date(4)]).unwrap();
+        old.insert(4, "same", vec![date(1)]).unwrap();
+
+        let new = Database::<Writeable>::open(&new_dir).unwrap();
+        new.insert(2, "foo", vec![date(2), date(5)]).unwrap();
+        new.insert(2, "qux", vec![date(6)]).unwrap();
+        new.insert(3, "bar", vec![date(2), date(3), date(4)])
+            .unwrap();
+        new.insert(4, "same", vec![date(1)]).unwrap();
+        new.insert(5, "added", vec![]).unwrap();
+        new.insert(5, "other", vec![]).unwrap();
+
+        let changes = old.diff(&new).collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_eq!(
+            changes,
+            vec![
+                Change::RemovedAccount { id: 1 },
+                Change::RemovedPair {
+                    id: 1,
+                    screen_name: "removed".to_string(),
+                    first_seen: Some(date(1)),
+                    last_seen: Some(date(1)),
+                },
+                Change::ExtendedRange {
+                    id: 2,
+                    screen_name: "foo".to_string(),
+                    old_first_seen: Some(date(2)),
+                    old_last_seen: Some(date(4)),
+                    new_first_seen: Some(date(2)),
+                    new_last_seen: Some(date(5)),
+                },
+                Change::RemovedPair {
+                    id: 2,
+                    screen_name: "gone".to_string(),
+                    first_seen: None,
+                    last_seen: None,
+                },
+                Change::NewPair {
+                    id: 2,
+                    screen_name: "qux".to_string(),
+                    first_seen: Some(date(6)),
+                    last_seen: Some(date(6)),
+                },
+                Change::ChangedObservations {
+                    id: 3,
+                    screen_name: "bar".to_string(),
+                },
+                Change::NewAccount { id: 5 },
+                Change::NewPair {
+                    id: 5,
+                    screen_name: "added".to_string(),
+                    first_seen: None,
+                    last_seen: None,
+                },
+                Change::NewPair {
+                    id: 5,
+                    screen_name: "other".to_string(),
+                    first_seen: None,
+                    last_seen: None,
+                },
+            ]
+        );
+
+        let mut counts = DiffCounts::default();
+        changes.iter().for_each(|change| counts.update(change));
+
+        assert_eq!(counts.new_accounts, 1);
+        assert_eq!(counts.removed_pairs, 2);
+        assert!(new.diff(&new).next().is_none());
+    }
+
+    #[test]
+    fn audit() {
+        use audit::{Anomaly, AuditCounts};
+
+        let date = |year, day| NaiveDate::from_ymd_opt(year, 1, day).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let quarantine_dir = tempfile::tempdir().unwrap();
+        let snowflake_id = 822215679726100480;
+
+        let db = Database::<Writeable>::open(&dir).unwrap();
+        db.insert(1, "foo", vec![date(2020, 1)]).unwrap();
+        db.insert(1, "FOO", vec![date(2020, 2)]).unwrap();
+        db.insert(1, "Foo", vec![]).unwrap();
+        db.insert(4, "future", vec![date(2030, 1)]).unwrap();
+        db.insert(snowflake_id, "early", vec![date(2016, 1), date(2018, 1)])
+            .unwrap();
+
+        let raw = db.accounts.underlying();
+        raw.put([&2u64.to_be_bytes()[..], b"foo-bar"].concat(), [])
+            .unwrap();
+        raw.put([&3u64.to_be_bytes()[..], b"baz"].concat(), [0, 1, 3])
+            .unwrap();
+        raw.put(b"abc", []).unwrap();
+
+        let findings = db
+            .audit(date(2025, 1))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let anomalies = findings
+            .iter()
+            .map(|finding| finding.anomaly.clone())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            anomalies,
+            vec![
+                Anomaly::EmptyDates {
+                    id: 1,
+                    screen_name: "Foo".to_string()
+                },
+                Anomaly::CaseDuplicate {
+                    id: 1,
+                    screen_names: vec!["FOO".to_string(), "Foo".to_string(), "foo".to_string()]
+                },
+                Anomaly::InvalidScreenName {
+                    id: 2,
+                    screen_name: "foo-bar".to_string()
+                },
+                Anomaly::InvalidValue {
+                    id: 3,
+                    screen_name: "baz".to_string()
+                },
+                Anomaly::FutureDate {
+                    id: 4,
+                    screen_name: "future".to_string(),
+                    last_seen: date(2030, 1)
+                },
+                Anomaly::BeforeCreation {
+                    id: snowflake_id,
+                    screen_name: "early".to_string(),
+                    first_seen: date(2016, 1),
+                    created: crate::snowflake::snowflake_to_date_time(snowflake_id).unwrap()
+                },
+                Anomaly::InvalidKey {
+                    key: b"abc".to_vec()
+                },
+            ]
+        );
+
+        let mut counts = AuditCounts::default();
+        anomalies.iter().for_each(|anomaly| counts.update(anomaly));
+        assert_eq!(counts.case_duplicates, 1);
+        assert_eq!(counts.invalid_keys, 1);
+
+        let quarantine = Database::<Writeable>::open(&quarantine_dir).unwrap();
+        let removed = findings
+            .iter()
+            .filter(|finding| db.remove_finding(finding, Some(&quarantine)).unwrap())
+            .count();
+
+        assert_eq!(removed, 5);
+        assert_eq!(
+            quarantine
+                .accounts
+                .underlying()
+                .iterator(IteratorMode::Start)
+                .count(),
+            5
+        );
+        assert_eq!(db.accounts.pairs().count(), 3);
+        assert_eq!(db.audit(date(2025, 1)).count(), 2);
+    }
+
+    #[test]
+    fn case_variants() {
+        use crate::model::{Account, CaseVariants};
+
+        let date = |day| NaiveDate::from_ymd_opt(2020, 1, day).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+
+        let db = Database::<Writeable>::open(&dir).unwrap();
+        db.insert(1, "foobar", vec![date(1), date(3)]).unwrap();
+        db.insert(1, "FooBar", vec![date(5), date(9)]).unwrap();
+        db.insert(1, "qux", vec![date(2)]).unwrap();
+        db.insert(2, "abc", vec![date(1)]).unwrap();
+        db.insert(2, "ABC", vec![date(2)]).unwrap();
+        db.insert(3, "abc", vec![date(3)]).unwrap();
+
+        let result = db.lookup_by_user_id(1).unwrap();
+        let kept = Account::from_raw_result(1, result.clone(), CaseVariants::Keep);
+        let merged = Account::from_raw_result(1, result, CaseVariants::Merge);
+
+        assert_eq!(
+            kept.screen_names.keys().collect::<Vec<_>>(),
+            vec!["foobar", "qux", "FooBar"]
+        );
+        assert!(kept.case_variants.is_empty());
+
+        assert_eq!(
+            merged.screen_names.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("FooBar".to_string(), Some(vec![date(1), date(9)])),
+                ("qux".to_string(), Some(vec![date(2)])),
+            ]
+        );
+        assert_eq!(
+            merged.case_variants["FooBar"]
+                .clone()
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![
+                ("foobar".to_string(), Some(vec![date(1), date(3)])),
+                ("FooBar".to_string(), Some(vec![date(5), date(9)])),
+            ]
+        );
+
+        assert_eq!(
+            db.accounts.get_case_only_renames().unwrap(),
+            vec![(2, vec!["ABC".to_string(), "abc".to_string()])]
+        );
+    }
+
+    #[test]
+    fn lookup_confusables() {
+        use confusables::skeleton;
+
+        assert_eq!(skeleton("E1on_Musk99"), "elonmusk");
+        assert_eq!(skeleton("c0rnell"), "comell");
+        assert_eq!(skeleton("12345"), "lzeas");
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = Database::<Writeable>::open(&dir).unwrap();
+        db.insert(1, "elonmusk", vec![]).unwrap();
+        db.insert(1, "ElonMusk", vec![]).unwrap();
+        db.insert(2, "e1onmusk", vec![]).unwrap();
+        db.insert(3, "elon_musk99", vec![]).unwrap();
+        db.insert(4, "elonmusks", vec![]).unwrap();
+
+        let expected = vec![
+            ("e1onmusk".to_string(), vec![2]),
+            ("elon_musk99".to_string(), vec![3]),
+            ("elonmusk".to_string(), vec![1]),
+        ];
+
+        assert_eq!(db.lookup_confusables("EIonMusk").unwrap(), expected);
+        assert!(db.lookup_confusables("foo").unwrap().is_empty());
+
+        db.accounts.remove(3, "elon_musk99").unwrap();
+        db.rebuild_index().unwrap();
+
+        assert_eq!(
+            db.lookup_confusables("elonmusk").unwrap(),
+            vec![expected[0].clone(), expected[2].clone()]
+        );
+        assert_eq!(db.confusables.get_counts().unwrap(), 3);
+    }
+
+    #[test]
+    fn import_records() {
+        use chrono::{TimeZone, Utc};
+        use metadata::{ImportRecord, ImportedFile};
+
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::<Writeable>::open(&dir).unwrap();
+
+        let file = |name: &str, sha256: &str| ImportedFile {
+            name: name.to_string(),
+            sha256: sha256.to_string(),
+            entries: 10,
+            size: Some(100),
+            modified: Some(Utc.with_ymd_and_hms(2020, 12, 31, 0, 0, 0).unwrap()),
+        };
+        let record = |path: &str| ImportRecord {
+            path: path.to_string(),
+            files: vec![file("names.csv", "aa"), file("profiles.ndjson", "bb")],
+            entries: 20,
+            imported_at: Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap(),
+            batch: None,
+        };
+
+        assert_eq!(db.metadata.get_import_record("/data/a").unwrap(), None);
+
+        db.metadata.put_import_record(&record("/data/b")).unwrap();
+        db.metadata.put_import_record(&record("/data/a")).unwrap();
+        db.metadata.put_format_version(FORMAT_VERSION).unwrap();
+
+        assert_eq!(
+            db.metadata.get_import_record("/data/a").unwrap(),
+            Some(record("/data/a"))
+        );
+        assert_eq!(
+            db.metadata.get_import_records().unwrap(),
+            vec![record("/data/a"), record("/data/b")]
+        );
+
+        let stored = record("/data/a");
+        assert!(stored.has_same_files(&[file("names.csv", "aa"), file("profiles.ndjson", "bb")]));
+        assert!(!stored.has_same_files(&[file("names.csv", "aa"), file("profiles.ndjson", "cc")]));
+        assert!(!stored.has_same_files(&[file("names.csv", "aa")]));
+
+        // Metadata comparisons ignore hashes, and never match files recorded without metadata.
+        let unhashed = [file("names.csv", ""), file("profiles.ndjson", "")];
+        assert!(stored.has_same_file_metadata(&unhashed));
+        assert!(!stored.has_same_file_metadata(&[
+            file("names.csv", ""),
+            ImportedFile {
+                size: Some(101),
+                ..file("profiles.ndjson", "")
+            }
+        ]));
+
+        let mut legacy = record("/data/c");
+        legacy.files[0].modified = None;
+        assert!(!legacy.has_same_file_metadata(&unhashed));
+    }
+
+    #[test]
+    fn rollback_import_batch() {
+        use chrono::{NaiveDate, TimeZone, Utc};
+        use metadata::{ImportBatch, ImportRecord};
+
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::<Writeable>::open(&dir).unwrap();
+        let date = |day: u32| NaiveDate::from_ymd_opt(2021, 1, day).unwrap();
+        let started_at = Utc.with_ymd_and_hms(2021, 2, 1, 0, 0, 0).unwrap();
+
+        let first = db.metadata.next_import_batch_id().unwrap();
+        let second = db.metadata.next_import_batch_id().unwrap();
+        assert_eq!((first, second), (1, 2));
+
+        for id in [first, second] {
+            db.metadata
+                .put_import_batch(&ImportBatch {
+                    id,
+                    command: "import-mentions".to_string(),
+                    operator: None,
+                    inputs: vec![],
+                    started_at,
+                    finished_at: Some(started_at),
+                    entries: 0,
+                    rejected: 0,
+                    pairs_recorded: true,
+                    rolled_back_at: None,
+                })
+                .unwrap();
+        }
+
+        let write = |batch: u64, id: u64, screen_name: &str, dates: Vec<NaiveDate>| {
+            db.insert_batch_observations(batch, id, screen_name, &Observations::new(dates, None))
+                .unwrap();
+        };
+
+        // Observed before the first batch, which observes it again.
+        db.insert(1, "foo", vec![date(6)]).unwrap();
+
+        write(first, 1, "foo", vec![date(1), date(5), date(6)]);
+        write(first, 2, "bar", vec![date(3)]);
+        write(second, 1, "foo", vec![date(2)]);
+        db.insert(3, "baz", vec![date(4)]).unwrap();
+        write(first, 3, "Baz", vec![date(7)]);
+
+        db.metadata
+            .put_import_record(&ImportRecord {
+                path: "/data/a".to_string(),
+                files: vec![],
+                entries: 3,
+                imported_at: started_at,
+                batch: Some(first),
+            })
+            .unwrap();
+
+        assert_eq!(
+            db.metadata.get_batch_pairs(first).unwrap(),
+            vec![
+                (1, "foo".to_string(), vec![date(1), date(5)]),
+                (2, "bar".to_string(), vec![date(3)]),
+                (3, "Baz".to_string(), vec![date(7)])
+            ]
+        );
+
+        let counts = db.rollback_import_batch(first).unwrap();
+
+        assert_eq!(
+            counts,
+            rollback::RollbackCounts {
+                updated_pairs: 1,
+                removed_pairs: 2,
+                removed_dates: 4,
+            }
+        );
+
+        let pairs = db
+            .accounts
+            .pairs()
+            .map(|pair| {
+                pair.map(|(id, screen_name, observations)| (id, screen_name, observations.dates))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            pairs,
+            vec![
+                (1, "foo".to_string(), vec![date(2), date(6)]),
+                (3, "baz".to_string(), vec![date(4)])
+            ]
+        );
+        assert!(db.lookup_by_screen_name("bar").unwrap().is_empty());
+        assert!(db.lookup_confusables("bar").unwrap().is_empty());
+        // Another pair for the same user still has the screen name in a different case.
+        assert_eq!(db.lookup_by_screen_name("baz").unwrap(), vec![3]);
+        assert_eq!(
+            db.lookup_confusables("baz").unwrap(),
+            vec![("baz".to_string(), vec![3])]
+        );
+        assert_eq!(db.metadata.get_import_record("/data/a").unwrap(), None);
+        assert!(db
+            .metadata
+            .get_import_batch(first)
+            .unwrap()
+            .unwrap()
+            .rolled_back_at
+            .is_some());
+
+        assert!(matches!(
+            db.rollback_import_batch(first),
+            Err(Error::BatchRolledBack(1))
+        ));
+        assert!(matches!(
+            db.rollback_import_batch(3),
+            Err(Error::UnknownImportBatch(3))
+        ));
+    }
+
+    #[test]
+    fn rollback_import_batch_range_policy() {
+        use chrono::{NaiveDate, TimeZone, Utc};
+        use metadata::ImportBatch;
+
+        let dir = tempfile::tempdir().unwrap();
+        Database::<Writeable>::open(&dir)
+            .unwrap()
+            .metadata
+            .put_merge_policy(MergePolicy::Range)
+            .unwrap();
+
+        let db = Database::<Writeable>::open(&dir).unwrap();
+        let date = |day: u32| NaiveDate::from_ymd_opt(2021, 1, day).unwrap();
+        let started_at = Utc.with_ymd_and_hms(2021, 2, 1, 0, 0, 0).unwrap();
+
+        let first = db.metadata.next_import_batch_id().unwrap();
+        let second = db.metadata.next_import_batch_id().unwrap();
+
+        for id in [first, second] {
+            db.metadata
+                .put_import_batch(&ImportBatch {
+                    id,
+                    command: "import-mentions".to_string(),
+                    operator: None,
+                    inputs: vec![],
+                    started_at,
+                    finished_at: Some(started_at),
+                    entries: 0,
+                    rejected: 0,
+                    pairs_recorded: true,
+                    rolled_back_at: None,
+                })
+                .unwrap();
+        }
+
+        let write = |batch: u64, dates: Vec<NaiveDate>| {
+            db.insert_batch_observations(batch, 1, "foo", &Observations::new(dates, None))
+                .unwrap();
+        };
+        let dates =
+            |db: &Database<Writeable>| db.lookup_by_user_id(1).unwrap()["foo"].dates.clone();
+
+        db.insert(1, "foo", vec![date(5), date(10)]).unwrap();
+
+        // The first batch extends the range in both directions, and then again in a later chunk.
+        write(first, vec![date(2), date(12)]);
+        write(first, vec![date(15)]);
+        write(second, vec![date(20)]);
+        assert_eq!(dates(&db), vec![date(2), date(20)]);
+
+        db.rollback_import_batch(first).unwrap();
+        assert_eq!(dates(&db), vec![date(5), date(20)]);
+
+        db.rollback_import_batch(second).unwrap();
+        assert_eq!(dates(&db), vec![date(5), date(10)]);
+    }
+
+    #[test]
+    fn merge_from() {
+        use merge::{MergeCounts, PairFilter};
+
+        let date = |day| NaiveDate::from_ymd_opt(2020, 1, day).unwrap();
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        let source = Database::<Writeable>::open(&source_dir).unwrap();
+        source.insert(1, "foo", vec![date(1)]).unwrap();
+        source.insert(2, "foo", vec![date(3), date(9)]).unwrap();
+        source.insert(2, "bar", vec![]).unwrap();
+        source.insert(3, "baz", vec![date(5)]).unwrap();
+        source.insert(4, "qux", vec![date(2)]).unwrap();
+
+        let target = Database::<Writeable>::open(&target_dir).unwrap();
+        target.insert(2, "foo", vec![date(4)]).unwrap();
+
+        let filter = PairFilter {
+            min_id: Some(2),
+            max_id: Some(3),
+            earliest: Some(date(3)),
+            latest: None,
+        };
+
+        assert_eq!(
+            target.merge_from(&source, &filter, 1).unwrap(),
+            MergeCounts {
+                merged_pairs: 2,
+                skipped_pairs: 1,
+            }
+        );
+
+        assert_eq!(
+            target.lookup_by_user_id(2).unwrap()["foo"].dates,
+            vec![date(3), date(4), date(9)]
+        );
+        assert!(!target.lookup_by_user_id(2).unwrap().contains_key("bar"));
+        assert!(target.lookup_by_user_id(1).unwrap().is_empty());
+        assert!(target.lookup_by_user_id(4).unwrap().is_empty());
+        assert_eq!(target.lookup_by_screen_name("foo").unwrap(), vec![2]);
+        assert_eq!(target.lookup_by_screen_name("baz").unwrap(), vec![3]);
+
+        target
+            .merge_from(&source, &PairFilter::default(), 100)
+            .unwrap();
+
+        assert_eq!(target.lookup_by_screen_name("foo").unwrap(), vec![1, 2]);
+        assert_eq!(target.get_counts().unwrap(), source.get_counts().unwrap());
+    }
+
+    #[test]
+    fn merge_from_with_prefix_extractor() {
+        use merge::PairFilter;
+
+        let options = DatabaseOptions {
+            bloom_filter_bits_per_key: Some(10.0),
+            account_id_prefix_extractor: true,
+            ..DatabaseOptions::default()
+        };
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        let source = Database::<Writeable>::open_with_options(&source_dir, &options).unwrap();
+
+        // Keep each ID in a separate file, so that a prefix seek could skip the later ones.
+        source
+            .accounts
+            .underlying()
+            .set_options(&[("disable_auto_compactions", "true")])
+            .unwrap();
+
+        for id in 1..=4 {
+            source.insert(id, "foo", vec![]).unwrap();
+            source.accounts.underlying().flush().unwrap();
+        }
+
+        let target = Database::<Writeable>::open_with_options(&target_dir, &options).unwrap();
+        let filter = PairFilter {
+            min_id: Some(2),
+            ..PairFilter::default()
+        };
+
+        assert_eq!(
+            target.merge_from(&source, &filter, 1).unwrap().merged_pairs,
+            3
+        );
+        assert_eq!(target.lookup_by_screen_name("foo").unwrap(), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn sharded() {
+        use sharded::ShardedDatabase;
+
+        let date = |day| NaiveDate::from_ymd_opt(2020, 1, day).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let manifest_path = dir.path().join("manifest.toml");
+
+        std::fs::write(
+            &manifest_path,
+            format!(
+                r#"
+                manifest_version = {MANIFEST_VERSION}
+                account_shards = [
+                    {{ path = "accounts-0", min_id = 0 }},
+                    {{ path = "accounts-1", min_id = 100 }},
+                    {{ path = "accounts-2", min_id = 200 }},
+                ]
+                screen_name_shards = ["screen-names-0", "screen-names-1"]
+                "#
+            ),
+        )
+        .unwrap();
+
+        let pairs = vec![
+            (1, "foo", vec![date(1)]),
+            (1, "bar", vec![date(2), date(3)]),
+            (99, "Foo", vec![]),
+            (100, "baz", vec![date(4)]),
+            (150, "foobar", vec![date(5)]),
+            (250, "bar", vec![date(6)]),
+            (u64::MAX, "qux", vec![date(7)]),
+        ];
+
+        let db = Database::<Writeable>::open(dir.path().join("single")).unwrap();
+        let sharded = ShardedDatabase::<Writeable>::open(&manifest_path).unwrap();
+
+        for (id, screen_name, dates) in pairs {
+            db.insert(id, screen_name, dates.clone()).unwrap();
+            sharded.insert(id, screen_name, dates).unwrap();
+        }
+
+        assert_eq!(sharded.get_counts().unwrap(), db.get_counts().unwrap());
+        assert_eq!(
+            sharded.pairs().collect::<Result<Vec<_>, _>>().unwrap(),
+            db.accounts.pairs().collect::<Result<Vec<_>, _>>().unwrap()
+        );
+
+        for id in [1, 99, 100, 150, 200, 250, u64::MAX] {
+            assert_eq!(
+                sharded.lookup_by_user_id(id).unwrap(),
+                db.lookup_by_user_id(id).unwrap()
+            );
+        }
+
+        for screen_name in ["foo", "BAR", "baz", "none"] {
+            assert_eq!(
+                sharded.lookup_by_screen_name(screen_name).unwrap(),
+                db.lookup_by_screen_name(screen_name).unwrap()
+            );
+        }
+
+        for (prefix, limit) in [("f", 10), ("f", 1), ("ba", 10), ("", 3)] {
+            assert_eq!(
+                sharded.lookup_by_screen_name_prefix(prefix, limit).unwrap(),
+                db.lookup_by_screen_name_prefix(prefix, limit).unwrap()
+            );
+        }
+
+        assert!(matches!(
+            sharded.lookup_confusables("foo"),
+            Err(Error::UnsupportedForShardedLayout(_))
+        ));
+
+        std::fs::write(
+            &manifest_path,
+            format!(
+                r#"
+                manifest_version = {MANIFEST_VERSION}
+                account_shards = [{{ path = "accounts-1", min_id = 100 }}]
+                screen_name_shards = ["screen-names-0"]
+                "#
+            ),
+        )
+        .unwrap();
+
+        assert!(matches!(
+            ShardedDatabase::<ReadOnly>::open(&manifest_path),
+            Err(Error::InvalidManifest(_))
+        ));
+
+        std::fs::write(
+            &manifest_path,
+            r#"
+            account_shards = [{ path = "accounts-0", min_id = 0 }]
+            screen_name_shards = ["screen-names-0"]
+            "#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            ShardedDatabase::<ReadOnly>::open(&manifest_path),
+            Err(Error::InvalidOptions(_))
+        ));
+
+        std::fs::write(
+            &manifest_path,
+            format!(
+                r#"
+                manifest_version = {FORMAT_VERSION}
+                account_shards = [{{ path = "accounts-0", min_id = 0 }}]
+                screen_name_shards = ["screen-names-0"]
+                "#
+            ),
+        )
+        .unwrap();
+
+        assert!(matches!(
+            ShardedDatabase::<ReadOnly>::open(&manifest_path),
+            Err(Error::UnsupportedManifestVersion(FORMAT_VERSION))
+        ));
+    }
 }
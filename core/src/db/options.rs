@@ -0,0 +1,140 @@
+use super::Error;
+use rocksdb::{BlockBasedOptions, Cache, DBCompressionType, Options, SliceTransform};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// The length of the user ID prefix of account table keys.
+const ACCOUNT_ID_PREFIX_LEN: usize = 8;
+
+// RocksDB's defaults for the compression options we don't expose.
+const DEFAULT_WINDOW_BITS: i32 = -14;
+const DEFAULT_COMPRESSION_LEVEL: i32 = 32767;
+const DEFAULT_STRATEGY: i32 = 0;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    #[default]
+    Snappy,
+    Zstd,
+}
+
+impl From<Compression> for DBCompressionType {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::None => DBCompressionType::None,
+            Compression::Snappy => DBCompressionType::Snappy,
+            Compression::Zstd => DBCompressionType::Zstd,
+        }
+    }
+}
+
+/// RocksDB tuning options that apply to the account table and screen name index.
+///
+/// All fields are optional, and the defaults match RocksDB's own defaults, so an empty TOML
+/// document is a valid configuration. For example:
+///
+/// ```toml
+/// compression = "zstd"
+/// zstd_level = 6
+/// zstd_dictionary_bytes = 16384
+/// bloom_filter_bits_per_key = 10.0
+/// account_id_prefix_extractor = true
+/// block_cache_bytes = 1073741824
+/// max_open_files = 1024
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseOptions {
+    pub compression: Compression,
+    /// Compression level when using zstd.
+    pub zstd_level: Option<i32>,
+    /// Maximum size of the dictionary that zstd trains for each file (disabled if not set).
+    pub zstd_dictionary_bytes: Option<u32>,
+    /// Maximum amount of sample data used to train dictionaries (defaults to one hundred times
+    /// the dictionary size).
+    pub zstd_max_train_bytes: Option<u32>,
+    pub bloom_filter_bits_per_key: Option<f64>,
+    /// Use the eight-byte user ID at the start of account table keys as a prefix, so that bloom
+    /// filters can skip files when looking up accounts.
+    pub account_id_prefix_extractor: bool,
+    /// Size of a block cache shared by both tables (RocksDB's default per-table cache if not set).
+    pub block_cache_bytes: Option<usize>,
+    pub max_open_files: Option<i32>,
+}
+
+impl DatabaseOptions {
+    pub fn from_toml_str(input: &str) -> Result<Self, Error> {
+        Ok(toml::from_str(input)?)
+    }
+
+    pub fn from_toml_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::from_toml_str(&std::fs::read_to_string(path)?)
+    }
+
+    pub(crate) fn make_block_cache(&self) -> Option<Cache> {
+        self.block_cache_bytes.map(Cache::new_lru_cache)
+    }
+
+    pub(crate) fn make_account_options(&self, cache: Option<&Cache>) -> Options {
+        let mut options = self.make_options(cache);
+
+        if self.account_id_prefix_extractor {
+            options
+                .set_prefix_extractor(SliceTransform::create_fixed_prefix(ACCOUNT_ID_PREFIX_LEN));
+        }
+
+        options
+    }
+
+    /// Screen name index keys have no fixed-length prefix, so no prefix extractor is used.
+    pub(crate) fn make_screen_name_options(&self, cache: Option<&Cache>) -> Options {
+        self.make_options(cache)
+    }
+
+    fn make_options(&self, cache: Option<&Cache>) -> Options {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        options.set_compression_type(self.compression.into());
+
+        if self.compression == Compression::Zstd {
+            let dictionary_bytes = self.zstd_dictionary_bytes.unwrap_or(0);
+
+            options.set_compression_options(
+                DEFAULT_WINDOW_BITS,
+                self.zstd_level.unwrap_or(DEFAULT_COMPRESSION_LEVEL),
+                DEFAULT_STRATEGY,
+                clamp_to_i32(dictionary_bytes),
+            );
+
+            if dictionary_bytes > 0 {
+                options.set_zstd_max_train_bytes(clamp_to_i32(
+                    self.zstd_max_train_bytes
+                        .unwrap_or_else(|| dictionary_bytes.saturating_mul(100)),
+                ));
+            }
+        }
+
+        if let Some(max_open_files) = self.max_open_files {
+            options.set_max_open_files(max_open_files);
+        }
+
+        let mut block_options = BlockBasedOptions::default();
+
+        if let Some(bits_per_key) = self.bloom_filter_bits_per_key {
+            block_options.set_bloom_filter(bits_per_key, false);
+        }
+
+        if let Some(cache) = cache {
+            block_options.set_block_cache(cache);
+        }
+
+        options.set_block_based_table_factory(&block_options);
+        options
+    }
+}
+
+fn clamp_to_i32(value: u32) -> i32 {
+    value.min(i32::MAX as u32) as i32
+}
@@ -0,0 +1,226 @@
+//! Undoing the observations written by an import batch.
+use super::{
+    accounts::Observations, table::Writeable, util::is_valid_screen_name, Database, Error,
+};
+use chrono::{NaiveDate, Utc};
+use std::collections::BTreeSet;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RollbackCounts {
+    /// Pairs that lost some observation dates.
+    pub updated_pairs: usize,
+    /// Pairs that lost all of their observation dates.
+    pub removed_pairs: usize,
+    pub removed_dates: usize,
+}
+
+impl Database<Writeable> {
+    /// Insert observations for a pair, recording the dates that the pair didn't already have for
+    /// the given import batch.
+    ///
+    /// If the observations extend the pair's range, the existing endpoints are also recorded,
+    /// since they are lost when the dates are reduced to a range (by the range merge policy or
+    /// `compact_ranges`), and a rollback has to restore them. The dates are recorded before the
+    /// observations are written, so that an interrupted write can't leave dates that a rollback
+    /// wouldn't remove.
+    pub fn insert_batch_observations(
+        &self,
+        batch: u64,
+        id: u64,
+        screen_name: &str,
+        observations: &Observations,
+    ) -> Result<(), Error> {
+        if !is_valid_screen_name(screen_name) {
+            return Err(Error::InvalidScreenName(screen_name.to_string()));
+        }
+
+        let existing = self
+            .accounts
+            .get(id, screen_name)?
+            .map(|existing| existing.dates)
+            .unwrap_or_default();
+        let new_dates = observations
+            .dates
+            .iter()
+            .filter(|date| !existing.contains(date))
+            .copied()
+            .collect::<Vec<_>>();
+
+        let displaced = match (existing.first(), existing.last()) {
+            (Some(first), Some(last)) => [
+                Some(*first).filter(|first| new_dates.iter().any(|date| date < first)),
+                Some(*last).filter(|last| new_dates.iter().any(|date| date > last)),
+            ]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>(),
+            _ => vec![],
+        };
+
+        if !new_dates.is_empty() {
+            self.metadata
+                .put_batch_pair(batch, id, screen_name, &new_dates)?;
+        }
+
+        if !displaced.is_empty() {
+            self.metadata
+                .put_batch_displaced_dates(batch, id, screen_name, &displaced)?;
+        }
+
+        self.insert_observations(id, screen_name, observations)
+    }
+
+    /// Remove the observation dates that were only contributed by the given import batch.
+    ///
+    /// A batch only records the dates that a pair didn't already have when they were written, so
+    /// dates that existed before the batch are kept, as are dates recorded for the same pair by
+    /// any other batch (that has not been rolled back). Range endpoints displaced by any batch are
+    /// restored unless they were written by a rolled back batch, so pairs whose dates have been
+    /// reduced to a range get back the range they had without the batch. A date that is only imported later by an
+    /// import that doesn't record its pairs can't be distinguished from the batch's own, and is
+    /// removed. Pairs that are left without any dates are removed (together with their screen name
+    /// and confusable index entries, if no other pair needs them), and the batch's import ledger
+    /// entries are deleted so that its inputs can be imported again.
+    pub fn rollback_import_batch(&self, id: u64) -> Result<RollbackCounts, Error> {
+        let mut batch = self
+            .metadata
+            .get_import_batch(id)?
+            .ok_or(Error::UnknownImportBatch(id))?;
+
+        if !batch.pairs_recorded {
+            return Err(Error::MissingBatchPairs(id));
+        }
+
+        if batch.rolled_back_at.is_some() {
+            return Err(Error::BatchRolledBack(id));
+        }
+
+        // Other batches that recorded their pairs, and whether they are still in effect.
+        let other_batches = self
+            .metadata
+            .get_import_batches()?
+            .into_iter()
+            .filter(|other| other.id != id && other.pairs_recorded)
+            .map(|other| (other.id, other.rolled_back_at.is_none()))
+            .collect::<Vec<_>>();
+
+        let mut counts = RollbackCounts::default();
+
+        for (user_id, screen_name, dates) in self.metadata.get_batch_pairs(id)? {
+            let current = match self.accounts.get(user_id, &screen_name)? {
+                Some(current) => current,
+                None => continue,
+            };
+
+            let mut other_dates = BTreeSet::new();
+            let mut rolled_back_dates = dates.iter().copied().collect::<BTreeSet<_>>();
+            let mut displaced = self
+                .metadata
+                .get_batch_displaced_dates(id, user_id, &screen_name)?
+                .unwrap_or_default()
+                .into_iter()
+                .collect::<BTreeSet<_>>();
+
+            for (other, in_effect) in &other_batches {
+                if let Some(dates) = self
+                    .metadata
+                    .get_batch_pair(*other, user_id, &screen_name)?
+                {
+                    if *in_effect {
+                        other_dates.extend(dates);
+                    } else {
+                        rolled_back_dates.extend(dates);
+                    }
+                }
+
+                if let Some(dates) =
+                    self.metadata
+                        .get_batch_displaced_dates(*other, user_id, &screen_name)?
+                {
+                    displaced.extend(dates);
+                }
+            }
+
+            let removed = dates
+                .iter()
+                .filter(|date| !other_dates.contains(date) && current.dates.contains(date))
+                .copied()
+                .collect::<BTreeSet<_>>();
+
+            if removed.is_empty() {
+                continue;
+            }
+
+            // With the range merge policy the stored dates may not include the other batches'
+            // dates or the endpoints that any batch displaced, so they are added back (and reduced
+            // to a range by the merge operator). Displaced dates that were themselves written by
+            // this or another rolled back batch are not restored.
+            let remaining = current
+                .dates
+                .iter()
+                .filter(|date| !removed.contains(date))
+                .chain(other_dates.iter())
+                .chain(displaced.difference(&rolled_back_dates))
+                .copied()
+                .collect::<BTreeSet<NaiveDate>>();
+
+            self.accounts.remove(user_id, &screen_name)?;
+            counts.removed_dates += removed.len();
+
+            match (remaining.first(), remaining.last()) {
+                (Some(first), Some(last)) => {
+                    let timestamps = current.timestamps.filter(|(first_seen, last_seen)| {
+                        first_seen.date_naive() == *first && last_seen.date_naive() == *last
+                    });
+                    let observations =
+                        Observations::new(remaining.iter().copied().collect(), timestamps);
+
+                    self.accounts
+                        .insert_observations(user_id, &screen_name, &observations)?;
+                    counts.updated_pairs += 1;
+                }
+                _ => {
+                    self.remove_from_index(user_id, &screen_name)?;
+                    counts.removed_pairs += 1;
+                }
+            }
+        }
+
+        for record in self.metadata.get_import_records()? {
+            if record.batch == Some(id) {
+                self.metadata.delete_import_record(&record.path)?;
+            }
+        }
+
+        batch.rolled_back_at = Some(Utc::now());
+        self.metadata.put_import_batch(&batch)?;
+
+        Ok(counts)
+    }
+
+    /// Update the screen name and confusable indices after a pair has been removed.
+    ///
+    /// The screen name index is case-insensitive, so the user's ID is kept if they have another
+    /// pair whose screen name only differs in case, and the confusable entry is kept if any user
+    /// still has the screen name.
+    fn remove_from_index(&self, id: u64, screen_name: &str) -> Result<(), Error> {
+        let lowercase = screen_name.to_lowercase();
+
+        if self
+            .accounts
+            .lookup(id)?
+            .keys()
+            .any(|other| other.to_lowercase() == lowercase)
+        {
+            return Ok(());
+        }
+
+        self.screen_names.remove(screen_name, id)?;
+
+        if self.screen_names.lookup(screen_name)?.is_empty() {
+            self.confusables.remove(screen_name)?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 use super::{
     accounts::AccountTable,
     table::{Mode, Table, Writeable},
+    util::{is_sorted_chunks, merge_sorted_chunks, sort_chunks},
     Error,
 };
 use rocksdb::{IteratorMode, MergeOperands, Options, DB};
@@ -8,6 +9,8 @@ use std::convert::TryInto;
 use std::marker::PhantomData;
 use std::path::Path;
 
+const ID_LEN: usize = 8;
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct ScreenNameTableCounts {
     pub screen_name_count: u64,
@@ -16,6 +19,7 @@ pub struct ScreenNameTableCounts {
 
 pub struct ScreenNameTable<M> {
     db: Option<DB>,
+    options: Options,
     mode: PhantomData<M>,
 }
 
@@ -38,8 +42,8 @@ impl<M> Table for ScreenNameTable<M> {
             screen_name_count += 1;
             let value_len = value.len();
 
-            if value_len % 8 == 0 {
-                mapping_count += value_len as u64 / 8;
+            if value_len.is_multiple_of(ID_LEN) {
+                mapping_count += (value_len / ID_LEN) as u64;
             } else {
                 return Err(Error::InvalidValue(value.to_vec()));
             }
@@ -53,13 +57,6 @@ impl<M> Table for ScreenNameTable<M> {
 }
 
 impl<M> ScreenNameTable<M> {
-    fn make_options() -> Options {
-        let mut options = Options::default();
-        options.create_if_missing(true);
-        options.set_merge_operator_associative("merge", merge);
-        options
-    }
-
     pub fn lookup(&self, screen_name: &str) -> Result<Vec<u64>, Error> {
         let value = self
             .db
@@ -123,8 +120,9 @@ impl<M> ScreenNameTable<M> {
 }
 
 impl<M: Mode> ScreenNameTable<M> {
-    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
-        let options = Self::make_options();
+    pub fn open<P: AsRef<Path>>(path: P, mut options: Options) -> Result<Self, Error> {
+        options.set_merge_operator_associative("merge", merge);
+
         let db = if M::is_read_only() {
             DB::open_for_read_only(&options, path, true)?
         } else {
@@ -133,6 +131,7 @@ impl<M: Mode> ScreenNameTable<M> {
 
         Ok(Self {
             db: Some(db),
+            options,
             mode: PhantomData,
         })
     }
@@ -147,15 +146,54 @@ impl ScreenNameTable<Writeable> {
             .merge(screen_name_to_key(screen_name), id.to_be_bytes())?)
     }
 
+    /// Remove an ID from a screen name's entry (and the entry if no IDs are left).
+    ///
+    /// This reads and rewrites the entry, so it must not run concurrently with inserts.
+    pub fn remove(&self, screen_name: &str, id: u64) -> Result<(), Error> {
+        let key = screen_name_to_key(screen_name);
+        let ids = self
+            .lookup(screen_name)?
+            .into_iter()
+            .filter(|other| *other != id)
+            .flat_map(u64::to_be_bytes)
+            .collect::<Vec<_>>();
+
+        if ids.is_empty() {
+            Ok(self.underlying().delete(key)?)
+        } else {
+            Ok(self.underlying().put(key, ids)?)
+        }
+    }
+
+    /// Rewrite any values whose IDs are not sorted and unique.
+    pub(crate) fn sort_values(&self) -> Result<usize, Error> {
+        let db = self.db.as_ref().unwrap();
+        let iter = db.iterator(IteratorMode::Start);
+        let mut count = 0;
+
+        for result in iter {
+            let (key, value) = result?;
+
+            if !value.len().is_multiple_of(ID_LEN) {
+                return Err(Error::InvalidValue(value.to_vec()));
+            }
+
+            if !is_sorted_chunks(&value, ID_LEN) {
+                db.put(key, sort_chunks(&value, ID_LEN))?;
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
     pub fn rebuild<Mode>(&mut self, accounts: &AccountTable<Mode>) -> Result<(), Error> {
         let path = self.db.as_ref().unwrap().path().to_path_buf();
         self.db.take().unwrap();
 
-        let options = Self::make_options();
+        DB::destroy(&self.options, &path)?;
 
-        DB::destroy(&options, &path)?;
-
-        self.db = Some(DB::open(&options, &path)?);
+        self.db = Some(DB::open(&self.options, &path)?);
 
         for pair in accounts.pairs() {
             let (id, screen_name, _) = pair?;
@@ -172,56 +210,20 @@ fn merge(
     existing_val: Option<&[u8]>,
     operands: &MergeOperands,
 ) -> Option<Vec<u8>> {
-    let mut new_val = match existing_val {
-        Some(bytes) => bytes.to_vec(),
-        None => Vec::with_capacity(operands.len() * 8),
-    };
+    let mut new_val = existing_val.map(|bytes| bytes.to_vec()).unwrap_or_default();
 
     for operand in operands.iter() {
-        merge_for_screen_name(&mut new_val, operand);
+        if operand.len().is_multiple_of(ID_LEN) {
+            new_val = merge_sorted_chunks(&new_val, operand, ID_LEN);
+        } else {
+            log::error!("Invalid screen name index operand: {:?}", operand);
+        }
     }
 
     Some(new_val)
 }
 
-fn merge_for_screen_name(a: &mut Vec<u8>, b: &[u8]) {
-    let original_len = a.len();
-    let mut i = 0;
-
-    while i < b.len() {
-        let bytes: [u8; 8] = match b[i..i + 8].try_into() {
-            Ok(bytes) => bytes,
-            Err(error) => {
-                log::error!("{}", error);
-                return;
-            }
-        };
-        let next_b = u64::from_be_bytes(bytes);
-
-        let mut found = false;
-        let mut j = 0;
-
-        while !found && j < original_len {
-            let bytes = match a[j..j + 8].try_into() {
-                Ok(bytes) => bytes,
-                Err(error) => {
-                    log::error!("{}", error);
-                    return;
-                }
-            };
-            let next_a = u64::from_be_bytes(bytes);
-            found = next_a == next_b;
-            j += 8;
-        }
-
-        if !found {
-            a.extend_from_slice(&b[i..i + 8]);
-        }
-        i += 8;
-    }
-}
-
-fn screen_name_to_key(screen_name: &str) -> Vec<u8> {
+pub(crate) fn screen_name_to_key(screen_name: &str) -> Vec<u8> {
     let form = screen_name.to_lowercase();
     form.as_bytes().to_vec()
 }
@@ -230,18 +232,19 @@ fn key_to_screen_name(key: &[u8]) -> Result<&str, Error> {
     Ok(std::str::from_utf8(key)?)
 }
 
-fn value_to_ids(value: &[u8]) -> Result<Vec<u64>, Error> {
-    let mut result = Vec::with_capacity(value.len() / 8);
+pub(crate) fn value_to_ids(value: &[u8]) -> Result<Vec<u64>, Error> {
+    let mut result = Vec::with_capacity(value.len() / ID_LEN);
     let mut i = 0;
 
     while i < value.len() {
         let id = u64::from_be_bytes(
-            value[i..i + 8]
-                .try_into()
-                .map_err(|_| Error::InvalidValue(value.to_vec()))?,
+            value
+                .get(i..i + ID_LEN)
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| Error::InvalidValue(value.to_vec()))?,
         );
         result.push(id);
-        i += 8;
+        i += ID_LEN;
     }
 
     Ok(result)
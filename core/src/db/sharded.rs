@@ -0,0 +1,264 @@
+//! A database whose tables are partitioned across multiple RocksDB directories.
+//!
+//! The account table is partitioned by user ID range, and the screen name index by a hash of the
+//! lowercased screen name. Sharded layouts have no confusable index or metadata table, so
+//! confusable lookups return [`Error::UnsupportedForShardedLayout`], and the import ledger, import
+//! batches, and rollback are only available for single databases. The layout is described by a
+//! TOML manifest, which has its own version number:
+//!
+//! ```toml
+//! manifest_version = 1
+//! account_shards = [
+//!   { path = "/disk1/accounts-0", min_id = 0 },
+//!   { path = "/disk2/accounts-1", min_id = 1000000000000000000 },
+//! ]
+//! screen_name_shards = ["/disk1/screen-names-0", "/disk2/screen-names-1"]
+//! ```
+//!
+//! Relative paths are resolved against the directory containing the manifest.
+use super::{
+    accounts::{AccountTable, AccountTableCounts, MergePolicy, Observations},
+    options::DatabaseOptions,
+    screen_names::{ScreenNameTable, ScreenNameTableCounts},
+    table::{Mode, Table, Writeable},
+    Error,
+};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// The current version of the manifest format and the sharded layout it describes.
+pub const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccountShard {
+    pub path: PathBuf,
+    /// The smallest user ID stored in this shard (shards are ordered by this value).
+    pub min_id: u64,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Required, so that shards written before a layout change are never opened unchecked.
+    pub manifest_version: u32,
+    #[serde(default)]
+    pub merge_policy: MergePolicy,
+    pub account_shards: Vec<AccountShard>,
+    pub screen_name_shards: Vec<PathBuf>,
+}
+
+impl Manifest {
+    pub fn from_toml_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let mut manifest: Self = toml::from_str(&std::fs::read_to_string(&path)?)?;
+
+        if let Some(base) = path.as_ref().parent() {
+            for shard in &mut manifest.account_shards {
+                shard.path = base.join(&shard.path);
+            }
+
+            for path in &mut manifest.screen_name_shards {
+                *path = base.join(&path);
+            }
+        }
+
+        manifest.validate()?;
+
+        Ok(manifest)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.manifest_version != MANIFEST_VERSION {
+            return Err(Error::UnsupportedManifestVersion(self.manifest_version));
+        }
+
+        if self.account_shards.first().map(|shard| shard.min_id) != Some(0) {
+            return Err(Error::InvalidManifest(
+                "the first account shard must start at user ID 0",
+            ));
+        }
+
+        if self
+            .account_shards
+            .windows(2)
+            .any(|shards| shards[0].min_id >= shards[1].min_id)
+        {
+            return Err(Error::InvalidManifest(
+                "account shards must be in increasing order of user ID",
+            ));
+        }
+
+        if self.screen_name_shards.is_empty() {
+            return Err(Error::InvalidManifest(
+                "at least one screen name shard is required",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+pub struct ShardedDatabase<M> {
+    min_ids: Vec<u64>,
+    accounts: Vec<AccountTable<M>>,
+    screen_names: Vec<ScreenNameTable<M>>,
+}
+
+impl<M: Mode> ShardedDatabase<M> {
+    pub fn open<P: AsRef<Path>>(manifest_path: P) -> Result<Self, Error> {
+        Self::open_with_options(manifest_path, &DatabaseOptions::default())
+    }
+
+    pub fn open_with_options<P: AsRef<Path>>(
+        manifest_path: P,
+        options: &DatabaseOptions,
+    ) -> Result<Self, Error> {
+        let manifest = Manifest::from_toml_file(manifest_path)?;
+        let cache = options.make_block_cache();
+
+        let accounts = manifest
+            .account_shards
+            .iter()
+            .map(|shard| {
+                AccountTable::open(
+                    &shard.path,
+                    manifest.merge_policy,
+                    options.make_account_options(cache.as_ref()),
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let screen_names = manifest
+            .screen_name_shards
+            .iter()
+            .map(|path| {
+                ScreenNameTable::open(path, options.make_screen_name_options(cache.as_ref()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            min_ids: manifest
+                .account_shards
+                .iter()
+                .map(|shard| shard.min_id)
+                .collect(),
+            accounts,
+            screen_names,
+        })
+    }
+}
+
+impl<M> ShardedDatabase<M> {
+    fn account_shard(&self, user_id: u64) -> &AccountTable<M> {
+        // The first shard always starts at zero, so the partition point is at least one.
+        let index = self.min_ids.partition_point(|min_id| *min_id <= user_id);
+        &self.accounts[index - 1]
+    }
+
+    fn screen_name_shard(&self, screen_name: &str) -> &ScreenNameTable<M> {
+        let hash = fnv1a(screen_name.to_lowercase().as_bytes());
+        &self.screen_names[(hash % self.screen_names.len() as u64) as usize]
+    }
+
+    pub fn get_counts(&self) -> Result<(AccountTableCounts, ScreenNameTableCounts), Error> {
+        let mut account_counts = AccountTableCounts {
+            id_count: 0,
+            pair_count: 0,
+        };
+        let mut screen_name_counts = ScreenNameTableCounts {
+            screen_name_count: 0,
+            mapping_count: 0,
+        };
+
+        for table in &self.accounts {
+            let counts = table.get_counts()?;
+            account_counts.id_count += counts.id_count;
+            account_counts.pair_count += counts.pair_count;
+        }
+
+        for table in &self.screen_names {
+            let counts = table.get_counts()?;
+            screen_name_counts.screen_name_count += counts.screen_name_count;
+            screen_name_counts.mapping_count += counts.mapping_count;
+        }
+
+        Ok((account_counts, screen_name_counts))
+    }
+
+    /// Iterate over all pairs in user ID and screen name order.
+    pub fn pairs(&self) -> impl Iterator<Item = Result<(u64, String, Observations), Error>> + '_ {
+        self.accounts.iter().flat_map(|table| table.pairs())
+    }
+
+    pub fn lookup_by_user_id(&self, user_id: u64) -> Result<HashMap<String, Observations>, Error> {
+        self.account_shard(user_id).lookup(user_id)
+    }
+
+    pub fn limited_lookup_by_user_id(
+        &self,
+        user_id: u64,
+        earliest: Option<NaiveDate>,
+    ) -> Result<HashMap<String, Observations>, Error> {
+        let table = self.account_shard(user_id);
+
+        match earliest {
+            Some(earliest) => table.limited_lookup(user_id, earliest),
+            None => table.lookup(user_id),
+        }
+    }
+
+    pub fn lookup_by_screen_name(&self, screen_name: &str) -> Result<Vec<u64>, Error> {
+        self.screen_name_shard(screen_name).lookup(screen_name)
+    }
+
+    /// Prefix searches query every screen name shard and return the first results in key order.
+    /// Sharded layouts have no confusable index.
+    pub fn lookup_confusables(&self, _screen_name: &str) -> Result<Vec<(String, Vec<u64>)>, Error> {
+        Err(Error::UnsupportedForShardedLayout("confusable lookups"))
+    }
+
+    pub fn lookup_by_screen_name_prefix(
+        &self,
+        screen_name_prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, Vec<u64>)>, Error> {
+        let mut results = vec![];
+
+        for table in &self.screen_names {
+            results.extend(table.lookup_by_prefix(screen_name_prefix, limit)?);
+        }
+
+        results.sort_by(|(a, _), (b, _)| a.cmp(b));
+        results.truncate(limit);
+
+        Ok(results)
+    }
+}
+
+impl ShardedDatabase<Writeable> {
+    pub fn insert(&self, id: u64, screen_name: &str, dates: Vec<NaiveDate>) -> Result<(), Error> {
+        self.insert_observations(id, screen_name, &Observations::new(dates, None))
+    }
+
+    pub fn insert_observations(
+        &self,
+        id: u64,
+        screen_name: &str,
+        observations: &Observations,
+    ) -> Result<(), Error> {
+        self.account_shard(id)
+            .insert_observations(id, screen_name, observations)?;
+        self.screen_name_shard(screen_name)
+            .insert(screen_name, id)?;
+        Ok(())
+    }
+}
+
+/// 64-bit FNV-1a (used because shard assignment must be stable across versions and platforms).
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
@@ -1,5 +1,5 @@
 use super::Error;
-use rocksdb::DB;
+use rocksdb::{DBIterator, IteratorMode, ReadOptions, DB};
 
 pub trait Mode {
     fn is_read_only() -> bool;
@@ -30,4 +30,12 @@ pub trait Table: Sized {
             .underlying()
             .property_int_value("rocksdb.estimate-num-keys")?)
     }
+
+    /// Iterate over the table in key order without being limited to the prefix of the first key
+    /// (which a prefix extractor would otherwise allow).
+    fn ordered_iterator(&self, mode: IteratorMode) -> DBIterator<'_> {
+        let mut options = ReadOptions::default();
+        options.set_total_order_seek(true);
+        self.underlying().iterator_opt(mode, options)
+    }
 }
@@ -1,5 +1,58 @@
+use std::cmp::Ordering;
+
 pub fn is_valid_screen_name(value: &str) -> bool {
     value
         .chars()
         .all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
 }
+
+/// Merge two sorted sequences of fixed-width big-endian integers, removing duplicates.
+///
+/// Big-endian encodings compare lexicographically in numeric order, so we can compare the chunks
+/// directly. Any trailing partial chunk is ignored.
+pub(crate) fn merge_sorted_chunks(a: &[u8], b: &[u8], width: usize) -> Vec<u8> {
+    let mut result = Vec::with_capacity(a.len() + b.len());
+    let mut a_chunks = a.chunks_exact(width).peekable();
+    let mut b_chunks = b.chunks_exact(width).peekable();
+
+    loop {
+        let next = match (a_chunks.peek(), b_chunks.peek()) {
+            (Some(next_a), Some(next_b)) => match next_a.cmp(next_b) {
+                Ordering::Less => a_chunks.next(),
+                Ordering::Greater => b_chunks.next(),
+                Ordering::Equal => {
+                    b_chunks.next();
+                    a_chunks.next()
+                }
+            },
+            (Some(_), None) => a_chunks.next(),
+            (None, Some(_)) => b_chunks.next(),
+            (None, None) => break,
+        };
+
+        if let Some(next) = next {
+            if !result.ends_with(next) {
+                result.extend_from_slice(next);
+            }
+        }
+    }
+
+    result
+}
+
+/// Sort a sequence of fixed-width big-endian integers and remove duplicates.
+pub(crate) fn sort_chunks(value: &[u8], width: usize) -> Vec<u8> {
+    let mut chunks = value.chunks_exact(width).collect::<Vec<_>>();
+    chunks.sort();
+    chunks.dedup();
+    chunks.concat()
+}
+
+/// Whether a sequence of fixed-width big-endian integers is strictly increasing.
+pub(crate) fn is_sorted_chunks(value: &[u8], width: usize) -> bool {
+    value.len().is_multiple_of(width)
+        && value
+            .chunks_exact(width)
+            .zip(value.chunks_exact(width).skip(1))
+            .all(|(a, b)| a < b)
+}
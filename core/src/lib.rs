@@ -1,2 +1,4 @@
 pub mod db;
 pub mod model;
+pub mod snowflake;
+pub mod store;
@@ -1,4 +1,6 @@
-use chrono::NaiveDate;
+use crate::db::accounts::Observations;
+use crate::snowflake::{self, CreationTime};
+use chrono::{DateTime, NaiveDate, Utc};
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -20,41 +22,194 @@ impl ScreenNameResult {
     }
 }
 
+/// The exact first and last observation times for a screen name.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Seen {
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// How screen names for an account that differ only in case are presented.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaseVariants {
+    /// Every case variant is listed as a separate screen name.
+    #[default]
+    Keep,
+    /// Case variants are listed under a single canonical screen name (the variant observed most
+    /// recently), with the date ranges for the individual variants in `case_variants`.
+    Merge,
+}
+
+type DateRange = Option<Vec<NaiveDate>>;
+
 #[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Account {
     pub id: u64,
     #[serde(rename = "screen_names")]
     pub screen_names: IndexMap<String, Option<Vec<NaiveDate>>>,
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub seen: IndexMap<String, Seen>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<CreationTime>,
+    /// Date ranges for each case variant of a canonical screen name (only for merged variants).
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub case_variants: IndexMap<String, IndexMap<String, Option<Vec<NaiveDate>>>>,
 }
 
 impl Account {
-    pub fn from_raw_result(id: u64, result: HashMap<String, Vec<NaiveDate>>) -> Self {
+    pub fn from_raw_result(
+        id: u64,
+        result: HashMap<String, Observations>,
+        case_variants: CaseVariants,
+    ) -> Self {
+        let (result, mut variant_ranges) = match case_variants {
+            CaseVariants::Keep => (result, HashMap::new()),
+            CaseVariants::Merge => merge_case_variants(result),
+        };
+
         let mut sorted = result
             .into_iter()
-            .map(|(screen_name, mut dates)| {
+            .map(|(screen_name, observations)| {
+                let mut dates = observations.dates;
                 dates.sort();
 
-                let value = match dates.len() {
-                    0 => None,
-                    1 => Some(vec![dates[0]]),
-                    n => Some(vec![dates[0], dates[n - 1]]),
-                };
+                let value = date_range(&dates);
 
-                (screen_name, value)
+                let seen = observations.timestamps.map(|(first_seen, last_seen)| Seen {
+                    first_seen,
+                    last_seen,
+                });
+
+                (screen_name, (value, seen))
             })
             .collect::<IndexMap<_, _>>();
 
-        sorted.sort_by(|screen_name_a, dates_a, screen_name_b, dates_b| {
-            dates_a
-                .as_ref()
-                .and_then(|dates| dates.first())
-                .cmp(&dates_b.as_ref().and_then(|dates| dates.first()))
-                .then_with(|| screen_name_a.cmp(screen_name_b))
-        });
+        // Screen names first observed on the same day are ordered by exact time where possible.
+        sorted.sort_by(
+            |screen_name_a, (dates_a, seen_a), screen_name_b, (dates_b, seen_b)| {
+                dates_a
+                    .as_ref()
+                    .and_then(|dates| dates.first())
+                    .cmp(&dates_b.as_ref().and_then(|dates| dates.first()))
+                    .then_with(|| {
+                        seen_a
+                            .map(|seen| seen.first_seen)
+                            .cmp(&seen_b.map(|seen| seen.first_seen))
+                    })
+                    .then_with(|| screen_name_a.cmp(screen_name_b))
+            },
+        );
+
+        let seen = sorted
+            .iter()
+            .filter_map(|(screen_name, (_, seen))| seen.map(|seen| (screen_name.to_string(), seen)))
+            .collect();
+
+        let case_variants = sorted
+            .keys()
+            .filter_map(|screen_name| {
+                variant_ranges
+                    .remove(screen_name)
+                    .map(|ranges| (screen_name.to_string(), ranges))
+            })
+            .collect();
 
         Self {
             id,
-            screen_names: sorted,
+            screen_names: sorted
+                .into_iter()
+                .map(|(screen_name, (dates, _))| (screen_name, dates))
+                .collect(),
+            seen,
+            created: snowflake::creation_time(id),
+            case_variants,
         }
     }
 }
+
+/// The first and last of the given sorted dates.
+fn date_range(dates: &[NaiveDate]) -> DateRange {
+    match dates.len() {
+        0 => None,
+        1 => Some(vec![dates[0]]),
+        n => Some(vec![dates[0], dates[n - 1]]),
+    }
+}
+
+/// Combine the observations for screen names that differ only in case, returning the combined
+/// observations for each canonical screen name together with the date ranges for each variant.
+fn merge_case_variants(
+    result: HashMap<String, Observations>,
+) -> (
+    HashMap<String, Observations>,
+    HashMap<String, IndexMap<String, DateRange>>,
+) {
+    let mut groups: HashMap<String, Vec<(String, Observations)>> = HashMap::new();
+
+    for (screen_name, mut observations) in result {
+        observations.dates.sort();
+        groups
+            .entry(screen_name.to_lowercase())
+            .or_default()
+            .push((screen_name, observations));
+    }
+
+    let mut merged = HashMap::with_capacity(groups.len());
+    let mut variant_ranges = HashMap::new();
+
+    for mut group in groups.into_values() {
+        if group.len() == 1 {
+            merged.extend(group);
+            continue;
+        }
+
+        group.sort_by(
+            |(screen_name_a, observations_a), (screen_name_b, observations_b)| {
+                observations_a
+                    .dates
+                    .first()
+                    .cmp(&observations_b.dates.first())
+                    .then_with(|| screen_name_a.cmp(screen_name_b))
+            },
+        );
+
+        let canonical = group
+            .iter()
+            .max_by(
+                |(screen_name_a, observations_a), (screen_name_b, observations_b)| {
+                    observations_a
+                        .dates
+                        .last()
+                        .cmp(&observations_b.dates.last())
+                        .then_with(|| observations_a.last_seen().cmp(&observations_b.last_seen()))
+                        .then_with(|| screen_name_b.cmp(screen_name_a))
+                },
+            )
+            .map(|(screen_name, _)| screen_name.clone())
+            .unwrap_or_default();
+
+        let mut dates = vec![];
+        let mut timestamps: Option<(DateTime<Utc>, DateTime<Utc>)> = None;
+        let mut ranges = IndexMap::with_capacity(group.len());
+
+        for (screen_name, observations) in group {
+            ranges.insert(screen_name, date_range(&observations.dates));
+            dates.extend(observations.dates);
+            timestamps = match (timestamps, observations.timestamps) {
+                (Some((first_a, last_a)), Some((first_b, last_b))) => {
+                    Some((first_a.min(first_b), last_a.max(last_b)))
+                }
+                (a, b) => a.or(b),
+            };
+        }
+
+        dates.sort();
+        dates.dedup();
+
+        merged.insert(canonical.clone(), Observations::new(dates, timestamps));
+        variant_ranges.insert(canonical, ranges);
+    }
+
+    (merged, variant_ranges)
+}
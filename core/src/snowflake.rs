@@ -0,0 +1,125 @@
+//! Account creation times derived from Twitter user IDs.
+//!
+//! User IDs assigned since late 2015 are snowflakes, which encode their creation time exactly.
+//! Older IDs were assigned sequentially, and for these we interpolate between known creation times.
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+/// The smallest ID that we treat as a snowflake.
+pub const FIRST_SNOWFLAKE: u64 = 250000000000000;
+
+const SNOWFLAKE_EPOCH_MILLIS: i64 = 1288834974657;
+
+/// Known creation times (as epoch seconds) for sequential user IDs, in increasing order of ID.
+///
+/// These are approximate, and only used for estimates.
+const CALIBRATION: [(u64, i64); 13] = [
+    (12, 1142974214),
+    (783214, 1171982154),
+    (813286, 1173132505),
+    (11348282, 1198095632),
+    (17919972, 1228558254),
+    (25073877, 1237383998),
+    (44196397, 1243973549),
+    (50393960, 1245869050),
+    (155659213, 1276542560),
+    (500704345, 1329989189),
+    (1339835893, 1364904000),
+    (2916305152, 1417435200),
+    // Roughly the last sequential IDs, assigned before the switch to snowflakes.
+    (4700000000, 1448841600),
+];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CreationTime {
+    pub timestamp: DateTime<Utc>,
+    /// Whether the timestamp is interpolated from the calibration table (as opposed to exact).
+    pub estimated: bool,
+}
+
+pub fn is_snowflake(value: u64) -> bool {
+    value >= FIRST_SNOWFLAKE
+}
+
+pub fn snowflake_to_date_time(value: u64) -> Option<DateTime<Utc>> {
+    if is_snowflake(value) {
+        let timestamp_millis = i64::try_from(value >> 22).ok()? + SNOWFLAKE_EPOCH_MILLIS;
+
+        Utc.timestamp_millis_opt(timestamp_millis).single()
+    } else {
+        None
+    }
+}
+
+/// Estimate the creation time of an account with a sequential ID.
+///
+/// Returns `None` for IDs outside the range covered by the calibration table.
+pub fn estimate_date_time(value: u64) -> Option<DateTime<Utc>> {
+    let index = CALIBRATION.partition_point(|(id, _)| *id <= value);
+
+    if index == 0 {
+        None
+    } else {
+        let (id_a, timestamp_a) = CALIBRATION[index - 1];
+
+        if id_a == value {
+            Utc.timestamp_opt(timestamp_a, 0).single()
+        } else {
+            let (id_b, timestamp_b) = CALIBRATION.get(index)?;
+            let offset = i128::from(value - id_a) * i128::from(timestamp_b - timestamp_a)
+                / i128::from(id_b - id_a);
+
+            Utc.timestamp_opt(timestamp_a + offset as i64, 0).single()
+        }
+    }
+}
+
+/// The exact creation time for snowflake IDs, or an estimate for sequential IDs.
+pub fn creation_time(value: u64) -> Option<CreationTime> {
+    snowflake_to_date_time(value)
+        .map(|timestamp| CreationTime {
+            timestamp,
+            estimated: false,
+        })
+        .or_else(|| {
+            estimate_date_time(value).map(|timestamp| CreationTime {
+                timestamp,
+                estimated: true,
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Datelike;
+
+    #[test]
+    fn creation_time() {
+        assert_eq!(
+            super::creation_time(822215679726100480),
+            Some(CreationTime {
+                timestamp: Utc.timestamp_millis_opt(1484866468732).unwrap(),
+                estimated: false
+            })
+        );
+        assert_eq!(
+            super::creation_time(813286),
+            Some(CreationTime {
+                timestamp: Utc.timestamp_opt(1173132505, 0).unwrap(),
+                estimated: true
+            })
+        );
+        assert_eq!(
+            super::creation_time(1000000).map(|created| created.timestamp.to_rfc3339()),
+            Some("2007-03-11T01:02:11+00:00".to_string())
+        );
+        assert_eq!(
+            super::creation_time(1_000_000_000)
+                .map(|created| (created.timestamp.year(), created.estimated)),
+            Some((2012, true))
+        );
+        assert_eq!(super::creation_time(1), None);
+        assert_eq!(super::creation_time(5_000_000_000), None);
+    }
+}
@@ -0,0 +1,377 @@
+//! Immutable, memory-mappable lookup files.
+//!
+//! A compact file contains the same information as the account table and screen name index of a
+//! [`Database`], in a single file that can be used for lookups without RocksDB. All integers are
+//! big-endian.
+//!
+//! * A fixed-size header containing the magic bytes, the file format version, the number of
+//!   pairs, and the offset and length of each of the following sections.
+//! * An array of 16-byte pair records (an 8-byte user ID and an 8-byte heap offset), sorted by
+//!   user ID and then screen name.
+//! * A heap of pair entries, each of which is a 2-byte screen name length, the UTF-8 screen name,
+//!   a 4-byte value length, and the value (encoded as in the account table).
+//! * An array of user ID lists, each of which is a 4-byte count followed by 8-byte user IDs.
+//! * An [FST](https://docs.rs/fst) map from lowercased screen names to the offset of their user
+//!   ID list.
+//!
+//! Because pair records have a fixed size and the sections are written in order, the exporter
+//! makes several passes over the database instead of buffering anything in memory.
+use crate::db::{
+    accounts::{self, Observations},
+    Database, Error, Mode, Table,
+};
+use chrono::NaiveDate;
+use fst::{automaton::Str, Automaton, IntoStreamer, Map, MapBuilder, Streamer};
+use memmap2::Mmap;
+use rocksdb::{IteratorMode, ReadOptions};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"MEMLOLCF";
+const COMPACT_FORMAT_VERSION: u32 = 1;
+const HEADER_LEN: usize = 88;
+const RECORD_LEN: usize = 16;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+struct Header {
+    pair_count: u64,
+    records: (u64, u64),
+    heap: (u64, u64),
+    id_lists: (u64, u64),
+    fst: (u64, u64),
+}
+
+impl Header {
+    fn to_bytes(self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+        bytes[0..8].copy_from_slice(MAGIC);
+        bytes[8..12].copy_from_slice(&COMPACT_FORMAT_VERSION.to_be_bytes());
+
+        let fields = [
+            self.pair_count,
+            self.records.0,
+            self.records.1,
+            self.heap.0,
+            self.heap.1,
+            self.id_lists.0,
+            self.id_lists.1,
+            self.fst.0,
+            self.fst.1,
+        ];
+
+        for (i, field) in fields.iter().enumerate() {
+            bytes[16 + i * 8..24 + i * 8].copy_from_slice(&field.to_be_bytes());
+        }
+
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < HEADER_LEN || &bytes[0..8] != MAGIC {
+            return Err(Error::InvalidCompactFile("missing header"));
+        }
+
+        let version = read_u32(bytes, 8)?;
+
+        if version != COMPACT_FORMAT_VERSION {
+            return Err(Error::InvalidCompactFile("unsupported version"));
+        }
+
+        let field = |i: usize| read_u64(bytes, 16 + i * 8);
+
+        Ok(Self {
+            pair_count: field(0)?,
+            records: (field(1)?, field(2)?),
+            heap: (field(3)?, field(4)?),
+            id_lists: (field(5)?, field(6)?),
+            fst: (field(7)?, field(8)?),
+        })
+    }
+}
+
+/// Write the account table and screen name index of a database to a compact file.
+///
+/// Every pass over a table reads from the same snapshot, so writes during the export can't make
+/// the passes disagree. Returns the number of pairs and the number of screen names written.
+pub fn export<M: Mode, P: AsRef<Path>>(db: &Database<M>, path: P) -> Result<(u64, u64), Error> {
+    let accounts = db.accounts.underlying().snapshot();
+    let screen_names = db.screen_names.underlying().snapshot();
+    let total_order = || {
+        let mut options = ReadOptions::default();
+        options.set_total_order_seek(true);
+        options
+    };
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    let mut header = Header::default();
+    let mut position = HEADER_LEN as u64;
+
+    writer.write_all(&header.to_bytes())?;
+
+    // Pair records, with heap offsets computed from the lengths of the entries.
+    let mut heap_offset: u64 = 0;
+
+    for result in accounts.iterator_opt(IteratorMode::Start, total_order()) {
+        let (key, value) = result?;
+        let (id, screen_name) = accounts::key_to_pair(&key)?;
+
+        writer.write_all(&id.to_be_bytes())?;
+        writer.write_all(&heap_offset.to_be_bytes())?;
+        heap_offset += heap_entry_len(screen_name, &value)?;
+        header.pair_count += 1;
+    }
+
+    header.records = (position, header.pair_count * RECORD_LEN as u64);
+    position += header.records.1;
+
+    // The heap entries for the pairs.
+    for result in accounts.iterator_opt(IteratorMode::Start, total_order()) {
+        let (key, value) = result?;
+        let (_, screen_name) = accounts::key_to_pair(&key)?;
+
+        writer.write_all(&(screen_name.len() as u16).to_be_bytes())?;
+        writer.write_all(screen_name.as_bytes())?;
+        writer.write_all(&(value.len() as u32).to_be_bytes())?;
+        writer.write_all(&value)?;
+    }
+
+    header.heap = (position, heap_offset);
+    position += header.heap.1;
+
+    // The user ID lists for the screen name index.
+    let mut id_lists_len = 0;
+
+    for result in screen_names.iterator(IteratorMode::Start) {
+        let (_, value) = result?;
+        let ids = crate::db::screen_names::value_to_ids(&value)?;
+
+        writer.write_all(&(ids.len() as u32).to_be_bytes())?;
+
+        for id in ids {
+            writer.write_all(&id.to_be_bytes())?;
+        }
+
+        id_lists_len += 4 + value.len() as u64;
+    }
+
+    header.id_lists = (position, id_lists_len);
+    position += header.id_lists.1;
+
+    // The FST mapping screen names to user ID lists (keys are already in lexicographic order).
+    let mut builder = MapBuilder::new(&mut writer)?;
+    let mut id_list_offset = 0;
+    let mut screen_name_count = 0;
+
+    for result in screen_names.iterator(IteratorMode::Start) {
+        let (key, value) = result?;
+
+        builder.insert(&key, id_list_offset)?;
+        id_list_offset += 4 + value.len() as u64;
+        screen_name_count += 1;
+    }
+
+    builder.finish()?;
+
+    let end = writer.stream_position()?;
+    header.fst = (position, end - position);
+
+    writer.seek(SeekFrom::Start(0))?;
+    writer.write_all(&header.to_bytes())?;
+    writer.flush()?;
+
+    Ok((header.pair_count, screen_name_count))
+}
+
+fn heap_entry_len(screen_name: &str, value: &[u8]) -> Result<u64, Error> {
+    if screen_name.len() > u16::MAX as usize || value.len() > u32::MAX as usize {
+        Err(Error::InvalidCompactFile("entry too large"))
+    } else {
+        Ok(2 + screen_name.len() as u64 + 4 + value.len() as u64)
+    }
+}
+
+/// A read-only store backed by a memory-mapped compact file.
+pub struct ReadOnlyStore {
+    mmap: Mmap,
+    header: Header,
+}
+
+impl ReadOnlyStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        // SAFETY: compact files are never modified after they are written.
+        let mmap = unsafe { Mmap::map(&file)? };
+        let header = Header::from_bytes(&mmap)?;
+
+        for (offset, len) in [header.records, header.heap, header.id_lists, header.fst] {
+            if offset
+                .checked_add(len)
+                .filter(|end| *end <= mmap.len() as u64)
+                .is_none()
+            {
+                return Err(Error::InvalidCompactFile("section out of bounds"));
+            }
+        }
+
+        if header.pair_count.checked_mul(RECORD_LEN as u64) != Some(header.records.1) {
+            return Err(Error::InvalidCompactFile("invalid record count"));
+        }
+
+        let store = Self { mmap, header };
+
+        // Validate the FST once so that later lookups can't fail on a malformed header.
+        store.names()?;
+
+        Ok(store)
+    }
+
+    pub fn pair_count(&self) -> u64 {
+        self.header.pair_count
+    }
+
+    pub fn lookup_by_user_id(&self, user_id: u64) -> Result<HashMap<String, Observations>, Error> {
+        self.lookup_filtered(user_id, |_| true)
+    }
+
+    pub fn limited_lookup_by_user_id(
+        &self,
+        user_id: u64,
+        earliest: Option<NaiveDate>,
+    ) -> Result<HashMap<String, Observations>, Error> {
+        match earliest {
+            Some(earliest) => {
+                self.lookup_filtered(user_id, |observations| observations.seen_since(earliest))
+            }
+            None => self.lookup_by_user_id(user_id),
+        }
+    }
+
+    pub fn lookup_by_screen_name(&self, screen_name: &str) -> Result<Vec<u64>, Error> {
+        match self.names()?.get(screen_name.to_lowercase()) {
+            Some(offset) => self.id_list(offset),
+            None => Ok(vec![]),
+        }
+    }
+
+    pub fn lookup_by_screen_name_prefix(
+        &self,
+        screen_name_prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, Vec<u64>)>, Error> {
+        let prefix = screen_name_prefix.to_lowercase();
+        let names = self.names()?;
+        let mut stream = names.search(Str::new(&prefix).starts_with()).into_stream();
+        let mut results = Vec::with_capacity(1);
+
+        while let Some((key, offset)) = stream.next() {
+            if results.len() >= limit {
+                break;
+            }
+
+            let screen_name = std::str::from_utf8(key)?.to_string();
+            results.push((screen_name, self.id_list(offset)?));
+        }
+
+        Ok(results)
+    }
+
+    fn lookup_filtered<F: Fn(&Observations) -> bool>(
+        &self,
+        user_id: u64,
+        include: F,
+    ) -> Result<HashMap<String, Observations>, Error> {
+        let records = self.section(self.header.records);
+        let count = self.header.pair_count as usize;
+
+        // Find the first record with the given user ID.
+        let mut low = 0;
+        let mut high = count;
+
+        while low < high {
+            let middle = low + (high - low) / 2;
+
+            if read_u64(records, middle * RECORD_LEN)? < user_id {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+
+        let mut results = HashMap::new();
+
+        for index in low..count {
+            if read_u64(records, index * RECORD_LEN)? != user_id {
+                break;
+            }
+
+            let (screen_name, value) =
+                self.heap_entry(read_u64(records, index * RECORD_LEN + 8)?)?;
+            let observations = accounts::value_to_observations(value)?;
+
+            if include(&observations) {
+                results.insert(screen_name.to_string(), observations);
+            }
+        }
+
+        Ok(results)
+    }
+
+    fn heap_entry(&self, offset: u64) -> Result<(&str, &[u8]), Error> {
+        let heap = self.section(self.header.heap);
+        let offset = offset as usize;
+        let screen_name_len = read_u16(heap, offset)? as usize;
+        let screen_name = slice(heap, offset + 2, screen_name_len)?;
+        let value_offset = offset + 2 + screen_name_len;
+        let value_len = read_u32(heap, value_offset)? as usize;
+        let value = slice(heap, value_offset + 4, value_len)?;
+
+        Ok((std::str::from_utf8(screen_name)?, value))
+    }
+
+    fn id_list(&self, offset: u64) -> Result<Vec<u64>, Error> {
+        let id_lists = self.section(self.header.id_lists);
+        let offset = offset as usize;
+        let count = read_u32(id_lists, offset)? as usize;
+
+        (0..count)
+            .map(|i| read_u64(id_lists, offset + 4 + i * 8))
+            .collect()
+    }
+
+    fn names(&self) -> Result<Map<&[u8]>, Error> {
+        Ok(Map::new(self.section(self.header.fst))?)
+    }
+
+    fn section(&self, (offset, len): (u64, u64)) -> &[u8] {
+        // Section bounds are checked when the file is opened.
+        &self.mmap[offset as usize..(offset + len) as usize]
+    }
+}
+
+fn slice(bytes: &[u8], offset: usize, len: usize) -> Result<&[u8], Error> {
+    offset
+        .checked_add(len)
+        .and_then(|end| bytes.get(offset..end))
+        .ok_or(Error::InvalidCompactFile("offset out of bounds"))
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, Error> {
+    Ok(u16::from_be_bytes(
+        slice(bytes, offset, 2)?.try_into().unwrap(),
+    ))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, Error> {
+    Ok(u32::from_be_bytes(
+        slice(bytes, offset, 4)?.try_into().unwrap(),
+    ))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64, Error> {
+    Ok(u64::from_be_bytes(
+        slice(bytes, offset, 8)?.try_into().unwrap(),
+    ))
+}
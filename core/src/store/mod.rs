@@ -0,0 +1,195 @@
+//! Read-only lookup backends.
+pub mod compact;
+
+use crate::db::{accounts::Observations, sharded::ShardedDatabase, Database, Error};
+use chrono::NaiveDate;
+use std::collections::HashMap;
+
+pub use compact::ReadOnlyStore;
+
+/// The lookups supported by both the RocksDB database and compact files.
+pub trait Store {
+    fn lookup_by_user_id(&self, user_id: u64) -> Result<HashMap<String, Observations>, Error>;
+
+    fn limited_lookup_by_user_id(
+        &self,
+        user_id: u64,
+        earliest: Option<NaiveDate>,
+    ) -> Result<HashMap<String, Observations>, Error>;
+
+    fn lookup_by_screen_name(&self, screen_name: &str) -> Result<Vec<u64>, Error>;
+
+    fn lookup_by_screen_name_prefix(
+        &self,
+        screen_name_prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, Vec<u64>)>, Error>;
+
+    /// Only RocksDB databases have a confusable index, so other backends return an error.
+    fn lookup_confusables(&self, _screen_name: &str) -> Result<Vec<(String, Vec<u64>)>, Error> {
+        Err(Error::UnsupportedLookup("confusables"))
+    }
+}
+
+impl<M: Sync + Send + 'static> Store for Database<M> {
+    fn lookup_by_user_id(&self, user_id: u64) -> Result<HashMap<String, Observations>, Error> {
+        Database::lookup_by_user_id(self, user_id)
+    }
+
+    fn limited_lookup_by_user_id(
+        &self,
+        user_id: u64,
+        earliest: Option<NaiveDate>,
+    ) -> Result<HashMap<String, Observations>, Error> {
+        Database::limited_lookup_by_user_id(self, user_id, earliest)
+    }
+
+    fn lookup_by_screen_name(&self, screen_name: &str) -> Result<Vec<u64>, Error> {
+        Database::lookup_by_screen_name(self, screen_name)
+    }
+
+    fn lookup_by_screen_name_prefix(
+        &self,
+        screen_name_prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, Vec<u64>)>, Error> {
+        Database::lookup_by_screen_name_prefix(self, screen_name_prefix, limit)
+    }
+
+    fn lookup_confusables(&self, screen_name: &str) -> Result<Vec<(String, Vec<u64>)>, Error> {
+        Database::lookup_confusables(self, screen_name)
+    }
+}
+
+impl<M> Store for ShardedDatabase<M> {
+    fn lookup_by_user_id(&self, user_id: u64) -> Result<HashMap<String, Observations>, Error> {
+        ShardedDatabase::lookup_by_user_id(self, user_id)
+    }
+
+    fn limited_lookup_by_user_id(
+        &self,
+        user_id: u64,
+        earliest: Option<NaiveDate>,
+    ) -> Result<HashMap<String, Observations>, Error> {
+        ShardedDatabase::limited_lookup_by_user_id(self, user_id, earliest)
+    }
+
+    fn lookup_by_screen_name(&self, screen_name: &str) -> Result<Vec<u64>, Error> {
+        ShardedDatabase::lookup_by_screen_name(self, screen_name)
+    }
+
+    fn lookup_by_screen_name_prefix(
+        &self,
+        screen_name_prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, Vec<u64>)>, Error> {
+        ShardedDatabase::lookup_by_screen_name_prefix(self, screen_name_prefix, limit)
+    }
+
+    fn lookup_confusables(&self, screen_name: &str) -> Result<Vec<(String, Vec<u64>)>, Error> {
+        ShardedDatabase::lookup_confusables(self, screen_name)
+    }
+}
+
+impl Store for ReadOnlyStore {
+    fn lookup_by_user_id(&self, user_id: u64) -> Result<HashMap<String, Observations>, Error> {
+        ReadOnlyStore::lookup_by_user_id(self, user_id)
+    }
+
+    fn limited_lookup_by_user_id(
+        &self,
+        user_id: u64,
+        earliest: Option<NaiveDate>,
+    ) -> Result<HashMap<String, Observations>, Error> {
+        ReadOnlyStore::limited_lookup_by_user_id(self, user_id, earliest)
+    }
+
+    fn lookup_by_screen_name(&self, screen_name: &str) -> Result<Vec<u64>, Error> {
+        ReadOnlyStore::lookup_by_screen_name(self, screen_name)
+    }
+
+    fn lookup_by_screen_name_prefix(
+        &self,
+        screen_name_prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, Vec<u64>)>, Error> {
+        ReadOnlyStore::lookup_by_screen_name_prefix(self, screen_name_prefix, limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{ReadOnly, Writeable};
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn compact_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let date = |day| NaiveDate::from_ymd_opt(2020, 1, day).unwrap();
+
+        {
+            let db = Database::<Writeable>::open(dir.path().join("db")).unwrap();
+            db.insert(123, "foo", vec![date(1), date(3)]).unwrap();
+            db.insert(123, "Bar", vec![date(5)]).unwrap();
+            db.insert(456, "FOO", vec![date(2)]).unwrap();
+            db.insert(456, "foobar", vec![]).unwrap();
+            db.insert_observations(
+                789,
+                "baz",
+                &Observations::new(
+                    vec![date(4)],
+                    Some((
+                        Utc.timestamp_opt(1578100000, 0).unwrap(),
+                        Utc.timestamp_opt(1578110000, 0).unwrap(),
+                    )),
+                ),
+            )
+            .unwrap();
+        }
+
+        let db = Database::<ReadOnly>::open(dir.path().join("db")).unwrap();
+        let path = dir.path().join("compact");
+
+        assert_eq!(compact::export(&db, &path).unwrap(), (5, 4));
+
+        let store = ReadOnlyStore::open(&path).unwrap();
+        let backends: [&dyn Store; 2] = [&db, &store];
+
+        for id in [0, 123, 456, 789, u64::MAX] {
+            assert_eq!(
+                backends[0].lookup_by_user_id(id).unwrap(),
+                backends[1].lookup_by_user_id(id).unwrap()
+            );
+            assert_eq!(
+                backends[0]
+                    .limited_lookup_by_user_id(id, Some(date(3)))
+                    .unwrap(),
+                backends[1]
+                    .limited_lookup_by_user_id(id, Some(date(3)))
+                    .unwrap()
+            );
+        }
+
+        for screen_name in ["foo", "BAR", "baz", "qux", ""] {
+            assert_eq!(
+                backends[0].lookup_by_screen_name(screen_name).unwrap(),
+                backends[1].lookup_by_screen_name(screen_name).unwrap()
+            );
+        }
+
+        for (prefix, limit) in [("f", 10), ("F", 1), ("ba", 10), ("q", 10), ("", 10)] {
+            assert_eq!(
+                backends[0]
+                    .lookup_by_screen_name_prefix(prefix, limit)
+                    .unwrap(),
+                backends[1]
+                    .lookup_by_screen_name_prefix(prefix, limit)
+                    .unwrap()
+            );
+        }
+
+        assert_eq!(store.lookup_by_screen_name("Foo").unwrap(), vec![123, 456]);
+        assert_eq!(store.pair_count(), 5);
+    }
+}
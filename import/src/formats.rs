@@ -0,0 +1,218 @@
+//! The built-in input formats.
+use crate::{Entries, Error, Importer, RejectKind, ScreenNameEntry};
+use chrono::{TimeZone, Utc};
+use serde_json::Value;
+use std::io::BufRead;
+
+/// CSV with at least three columns (user ID, screen name, and observation time as epoch second).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Mentions;
+
+impl Importer for Mentions {
+    fn name(&self) -> &str {
+        "mentions"
+    }
+
+    fn description(&self) -> &str {
+        "CSV with user ID, screen name, and epoch second columns"
+    }
+
+    /// The first line must start with `<digits>,<name>,<digits>`.
+    fn detect(&self, first_line: &[u8]) -> bool {
+        let is_number = |field: &[u8]| !field.is_empty() && field.iter().all(u8::is_ascii_digit);
+        let mut fields = first_line.trim_ascii().split(|byte| *byte == b',');
+
+        fields.next().filter(|field| is_number(field)).is_some()
+            && fields.next().filter(|field| !field.is_empty()).is_some()
+            && fields.next().filter(|field| is_number(field)).is_some()
+    }
+
+    fn entries<'a>(&self, source: Box<dyn BufRead + 'a>) -> Entries<'a> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(source);
+        let mut record = csv::StringRecord::new();
+
+        Box::new(std::iter::from_fn(move || {
+            match reader.read_record(&mut record) {
+                Ok(true) => {
+                    let line = record
+                        .position()
+                        .map(|position| position.line())
+                        .unwrap_or_default();
+
+                    Some(ScreenNameEntry::from_record_opt(&record).ok_or_else(|| {
+                        Error::InvalidLine {
+                            line,
+                            kind: RejectKind::Fields,
+                            detail: "Missing or invalid user ID, screen name, or timestamp"
+                                .to_string(),
+                            contents: record.iter().collect::<Vec<_>>().join(","),
+                        }
+                    }))
+                }
+                Ok(false) => None,
+                Err(error) if error.is_io_error() => Some(Err(error.into())),
+                Err(error) => Some(Err(Error::InvalidLine {
+                    line: error
+                        .position()
+                        .map(|position| position.line())
+                        .unwrap_or_default(),
+                    kind: RejectKind::Csv,
+                    detail: error.to_string(),
+                    contents: String::new(),
+                })),
+            }
+        }))
+    }
+}
+
+/// NDJSON Twitter user objects with an additional `snapshot` field (the observation time as epoch
+/// second).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Profiles;
+
+impl Importer for Profiles {
+    fn name(&self) -> &str {
+        "profiles"
+    }
+
+    fn description(&self) -> &str {
+        "NDJSON user objects with a snapshot field"
+    }
+
+    fn detect(&self, first_line: &[u8]) -> bool {
+        let trimmed = first_line.trim_ascii_start();
+
+        trimmed.starts_with(b"{")
+            && match serde_json::from_slice::<Value>(trimmed) {
+                Ok(value) => value.get("snapshot").is_some(),
+                // The line may be truncated.
+                Err(_) => trimmed
+                    .windows(b"\"snapshot\"".len())
+                    .any(|window| window == b"\"snapshot\""),
+            }
+    }
+
+    fn entries<'a>(&self, source: Box<dyn BufRead + 'a>) -> Entries<'a> {
+        lines(source, |line_number, line| {
+            let value = parse_json(line_number, line)?;
+
+            ScreenNameEntry::from_json(&value)
+                .map(|entry| vec![entry])
+                .ok_or_else(|| Error::InvalidLine {
+                    line: line_number,
+                    kind: RejectKind::Fields,
+                    detail: "Missing or invalid id_str, screen_name, or snapshot".to_string(),
+                    contents: line.to_string(),
+                })
+        })
+    }
+}
+
+/// NDJSON Twitter API v1.1 tweets (such as raw Stream Grab files) or API v2 responses.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Tweets;
+
+impl Importer for Tweets {
+    fn name(&self) -> &str {
+        "tweets"
+    }
+
+    fn description(&self) -> &str {
+        "NDJSON v1.1 tweets or v2 responses"
+    }
+
+    fn detect(&self, first_line: &[u8]) -> bool {
+        first_line.trim_ascii_start().starts_with(b"{")
+    }
+
+    fn entries<'a>(&self, source: Box<dyn BufRead + 'a>) -> Entries<'a> {
+        lines(source, |line_number, line| {
+            if line.trim().is_empty() {
+                Ok(vec![])
+            } else {
+                Ok(crate::tweets::extract_entries(&parse_json(
+                    line_number,
+                    line,
+                )?))
+            }
+        })
+    }
+}
+
+/// CSV with a user ID, a screen name, and any number of epoch second timestamps per row.
+///
+/// This format is never detected, since it cannot be distinguished from mentions.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Multi;
+
+impl Importer for Multi {
+    fn name(&self) -> &str {
+        "multi"
+    }
+
+    fn description(&self) -> &str {
+        "CSV with user ID, screen name, and any number of epoch second columns"
+    }
+
+    fn entries<'a>(&self, source: Box<dyn BufRead + 'a>) -> Entries<'a> {
+        lines(source, |line_number, line| {
+            parse_multi_line(line)
+                .map(|entry| vec![entry])
+                .ok_or_else(|| Error::InvalidLine {
+                    line: line_number,
+                    kind: RejectKind::Fields,
+                    detail: "Missing or invalid user ID, screen name, or timestamps".to_string(),
+                    contents: line.to_string(),
+                })
+        })
+    }
+}
+
+/// Parse each line (numbered from 1) into any number of entries.
+fn lines<'a, F>(source: Box<dyn BufRead + 'a>, parse: F) -> Entries<'a>
+where
+    F: Fn(u64, &str) -> Result<Vec<ScreenNameEntry>, Error> + 'a,
+{
+    Box::new(source.lines().enumerate().flat_map(move |(index, line)| {
+        let (entries, error) = match line
+            .map_err(Error::from)
+            .and_then(|line| parse(index as u64 + 1, &line))
+        {
+            Ok(entries) => (entries, None),
+            Err(error) => (vec![], Some(error)),
+        };
+
+        entries.into_iter().map(Ok).chain(error.map(Err))
+    }))
+}
+
+fn parse_json(line_number: u64, line: &str) -> Result<Value, Error> {
+    serde_json::from_str(line).map_err(|error| Error::InvalidLine {
+        line: line_number,
+        kind: RejectKind::Json,
+        detail: error.to_string(),
+        contents: line.to_string(),
+    })
+}
+
+fn parse_multi_line(line: &str) -> Option<ScreenNameEntry> {
+    let mut parts = line.split(',');
+    let id = parts.next()?.parse::<u64>().ok()?;
+    let screen_name = parts.next()?.to_string();
+    let snapshots = parts
+        .map(|part| {
+            part.parse::<i64>()
+                .ok()
+                .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single())
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(ScreenNameEntry {
+        id,
+        screen_name,
+        snapshots,
+        source: None,
+    })
+}
@@ -0,0 +1,129 @@
+//! Input formats for memory.lol imports.
+//!
+//! An [`Importer`] turns a byte stream into observations of ID-screen name pairs, and a
+//! [`Registry`] collects importers by name so that applications can select them by name or detect
+//! them from the content of a file. New formats can be added by implementing [`Importer`] and
+//! registering it.
+use chrono::{DateTime, TimeZone, Utc};
+use serde_json::Value;
+use std::fmt::{Display, Formatter};
+use std::io::BufRead;
+
+pub mod formats;
+pub mod registry;
+pub mod tweets;
+
+pub use registry::Registry;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("CSV error")]
+    Csv(#[from] csv::Error),
+    /// A line that cannot be imported (reading can continue with the next line).
+    #[error("Invalid input line")]
+    InvalidLine {
+        line: u64,
+        kind: RejectKind,
+        detail: String,
+        contents: String,
+    },
+    /// An error from a format defined outside this crate.
+    #[error("Format error")]
+    Format(#[from] Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// Why an input line could not be imported.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum RejectKind {
+    /// The line is not valid JSON.
+    Json,
+    /// The line is not a valid CSV record.
+    Csv,
+    /// Required fields are missing or cannot be parsed.
+    Fields,
+    /// A record in a container format (such as a WARC record or an archive data file) is malformed.
+    Record,
+    /// The screen name contains characters that screen names cannot have.
+    ScreenName,
+}
+
+impl Display for RejectKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            RejectKind::Json => "invalid-json",
+            RejectKind::Csv => "invalid-csv",
+            RejectKind::Fields => "invalid-fields",
+            RejectKind::Record => "invalid-record",
+            RejectKind::ScreenName => "invalid-screen-name",
+        })
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ScreenNameEntry {
+    pub id: u64,
+    pub screen_name: String,
+    pub snapshots: Vec<DateTime<Utc>>,
+    /// Where the observation was found (for example the URL of an archived page), if known.
+    pub source: Option<String>,
+}
+
+impl ScreenNameEntry {
+    pub fn from_json(value: &Value) -> Option<Self> {
+        let id_str_value = value.get("id_str")?;
+        let id_str_string = id_str_value.as_str()?;
+        let id = id_str_string.parse::<u64>().ok()?;
+        let screen_name_value = value.get("screen_name")?;
+        let screen_name = screen_name_value.as_str()?.to_string();
+        let snapshot_value = value.get("snapshot")?;
+        let snapshot = Utc.timestamp_opt(snapshot_value.as_i64()?, 0).single()?;
+        let snapshots = vec![snapshot];
+
+        Some(Self {
+            id,
+            screen_name,
+            snapshots,
+            source: None,
+        })
+    }
+
+    pub fn from_record_opt(record: &csv::StringRecord) -> Option<Self> {
+        let id = record.get(0).and_then(|value| value.parse::<u64>().ok())?;
+        let screen_name = record.get(1)?.to_string();
+        let snapshot_value = record.get(2).and_then(|value| value.parse::<i64>().ok())?;
+        let snapshot = Utc.timestamp_opt(snapshot_value, 0).single()?;
+        let snapshots = vec![snapshot];
+
+        Some(Self {
+            id,
+            screen_name,
+            snapshots,
+            source: None,
+        })
+    }
+}
+
+/// The entries read from an input, including errors for lines that cannot be imported.
+pub type Entries<'a> = Box<dyn Iterator<Item = Result<ScreenNameEntry, Error>> + 'a>;
+
+/// A named input format.
+pub trait Importer: Send + Sync {
+    /// The name used to select the format (for example `mentions`).
+    fn name(&self) -> &str;
+
+    fn description(&self) -> &str;
+
+    /// Whether decompressed content with this first line (which may be truncated) appears to be in
+    /// this format (by default formats are never detected and have to be selected by name).
+    fn detect(&self, _first_line: &[u8]) -> bool {
+        false
+    }
+
+    /// Read the entries from decompressed content.
+    ///
+    /// Lines that cannot be imported are returned as [`Error::InvalidLine`], and reading may
+    /// continue after them. Any other error means that the rest of the input cannot be read.
+    fn entries<'a>(&self, source: Box<dyn BufRead + 'a>) -> Entries<'a>;
+}
@@ -0,0 +1,187 @@
+//! Lookup and detection of input formats by name.
+use crate::formats::{Mentions, Multi, Profiles, Tweets};
+use crate::Importer;
+
+/// A collection of named input formats.
+///
+/// Formats are detected in reverse order of registration, so formats registered later (for
+/// example by an application) are tried before the built-in formats. A format that replaces another
+/// with the same name takes its place.
+pub struct Registry {
+    importers: Vec<Box<dyn Importer>>,
+}
+
+impl Registry {
+    /// A registry without any formats.
+    pub fn empty() -> Self {
+        Self { importers: vec![] }
+    }
+
+    /// Add a format, replacing any format with the same name.
+    pub fn register<I: Importer + 'static>(&mut self, importer: I) {
+        match self
+            .importers
+            .iter_mut()
+            .find(|existing| existing.name() == importer.name())
+        {
+            Some(existing) => *existing = Box::new(importer),
+            None => self.importers.push(Box::new(importer)),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Importer> {
+        self.iter().find(|importer| importer.name() == name)
+    }
+
+    /// The first format that recognizes content with this first line.
+    pub fn detect(&self, first_line: &[u8]) -> Option<&dyn Importer> {
+        self.iter().find(|importer| importer.detect(first_line))
+    }
+
+    /// All formats, in detection order.
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Importer> + '_ {
+        self.importers
+            .iter()
+            .rev()
+            .map(|importer| importer.as_ref())
+    }
+}
+
+impl Default for Registry {
+    /// A registry with the built-in formats.
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(Multi);
+        registry.register(Mentions);
+        registry.register(Tweets);
+        registry.register(Profiles);
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Entries, Error, RejectKind};
+    use std::io::BufRead;
+
+    fn names(entries: Entries<'_>) -> Vec<Result<(u64, String), RejectKind>> {
+        entries
+            .map(|entry| match entry {
+                Ok(entry) => Ok((entry.id, entry.screen_name)),
+                Err(Error::InvalidLine { kind, .. }) => Err(kind),
+                Err(error) => panic!("Unexpected error: {error:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detect() {
+        let registry = Registry::default();
+        let detected = |first_line: &[u8]| registry.detect(first_line).map(|format| format.name());
+
+        assert_eq!(detected(b"123,foo,1600000000\n"), Some("mentions"));
+        assert_eq!(
+            detected(br#"{"id_str": "123", "screen_name": "foo", "snapshot": 1600000000}"#),
+            Some("profiles")
+        );
+        assert_eq!(detected(br#"{"id_str": "123", "screen_na"#), Some("tweets"));
+        assert_eq!(
+            detected(br#"{"id_str": "123", "snapshot": 16"#),
+            Some("profiles")
+        );
+        assert_eq!(detected(b"screen_name,id\n"), None);
+        assert_eq!(detected(b"123,,1600000000\n"), None);
+        assert_eq!(detected(b"123,foo\n"), None);
+        assert_eq!(detected(b"2020-01-01,foo,1\n"), None);
+        assert_eq!(detected(b""), None);
+    }
+
+    #[test]
+    fn register() {
+        struct Custom;
+
+        impl Importer for Custom {
+            fn name(&self) -> &str {
+                "mentions"
+            }
+
+            fn description(&self) -> &str {
+                "Mentions with a header row"
+            }
+
+            fn detect(&self, first_line: &[u8]) -> bool {
+                first_line.starts_with(b"id,")
+            }
+
+            fn entries<'a>(&self, mut source: Box<dyn BufRead + 'a>) -> Entries<'a> {
+                let mut header = String::new();
+
+                match source.read_line(&mut header) {
+                    Ok(_) => Mentions.entries(source),
+                    Err(error) => Box::new(std::iter::once(Err(error.into()))),
+                }
+            }
+        }
+
+        let mut registry = Registry::default();
+        registry.register(Custom);
+
+        assert_eq!(registry.iter().count(), 4);
+        assert_eq!(
+            registry.get("mentions").map(|format| format.description()),
+            Some("Mentions with a header row")
+        );
+        assert_eq!(
+            registry
+                .detect(b"id,screen_name,timestamp")
+                .map(|format| format.name()),
+            Some("mentions")
+        );
+        assert!(registry.detect(b"123,foo,1600000000").is_none());
+    }
+
+    #[test]
+    fn entries() {
+        let registry = Registry::default();
+        let read = |name: &str, input: &'static str| {
+            names(
+                registry
+                    .get(name)
+                    .unwrap()
+                    .entries(Box::new(input.as_bytes())),
+            )
+        };
+
+        assert_eq!(
+            read(
+                "mentions",
+                "1,foo,1600000000\nx,bar,1600000000\n2,baz,1600000000\n"
+            ),
+            vec![
+                Ok((1, "foo".to_string())),
+                Err(RejectKind::Fields),
+                Ok((2, "baz".to_string()))
+            ]
+        );
+        assert_eq!(
+            read(
+                "profiles",
+                "{\"id_str\":\"1\",\"screen_name\":\"foo\",\"snapshot\":1600000000}\n{\n{}\n"
+            ),
+            vec![
+                Ok((1, "foo".to_string())),
+                Err(RejectKind::Json),
+                Err(RejectKind::Fields)
+            ]
+        );
+        assert_eq!(
+            read("multi", "1,foo,1600000000,1600100000\n2,bar\n3\n"),
+            vec![
+                Ok((1, "foo".to_string())),
+                Ok((2, "bar".to_string())),
+                Err(RejectKind::Fields)
+            ]
+        );
+    }
+}
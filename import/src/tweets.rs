@@ -0,0 +1,263 @@
+//! Extraction of ID-screen name observations from full tweet objects.
+//!
+//! Both Twitter API v1.1 tweets (including Stream Grab output) and API v2 responses are supported.
+//! User objects (the author and the authors of retweeted and quoted tweets, or v2 `includes.users`)
+//! are observed at the creation time of the outer tweet, since they are hydrated when the tweet is
+//! delivered. Mentions and reply targets are part of the tweet itself, so they are observed at the
+//! creation time of the tweet that contains them.
+use crate::ScreenNameEntry;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+/// The v1.1 `created_at` format (for example `Wed Oct 10 20:19:24 +0000 2018`).
+const V1_DATE_FORMAT: &str = "%a %b %d %H:%M:%S %z %Y";
+
+/// All observations in a v1.1 tweet or v2 response.
+///
+/// Values without a parseable creation time (such as deletion notices) produce no entries.
+pub fn extract_entries(value: &Value) -> Vec<ScreenNameEntry> {
+    let mut entries = vec![];
+
+    if value.get("data").is_some() {
+        extract_v2(value, &mut entries);
+    } else if let Some(created_at) = parse_v1_created_at(value) {
+        extract_v1(value, created_at, &mut entries);
+    }
+
+    entries
+}
+
+fn extract_v1(status: &Value, observed_at: DateTime<Utc>, entries: &mut Vec<ScreenNameEntry>) {
+    let created_at = parse_v1_created_at(status).unwrap_or(observed_at);
+
+    if let Some(user) = status.get("user") {
+        push_entry(
+            entries,
+            user.get("id_str"),
+            user.get("screen_name"),
+            observed_at,
+        );
+    }
+
+    let mentions = status
+        .get("extended_tweet")
+        .and_then(|extended| extended.get("entities"))
+        .or_else(|| status.get("entities"))
+        .and_then(|entities| entities.get("user_mentions"))
+        .and_then(|mentions| mentions.as_array());
+
+    for mention in mentions.into_iter().flatten() {
+        push_entry(
+            entries,
+            mention.get("id_str"),
+            mention.get("screen_name"),
+            created_at,
+        );
+    }
+
+    push_entry(
+        entries,
+        status.get("in_reply_to_user_id_str"),
+        status.get("in_reply_to_screen_name"),
+        created_at,
+    );
+
+    for field in ["retweeted_status", "quoted_status"] {
+        if let Some(embedded) = status.get(field).filter(|embedded| embedded.is_object()) {
+            extract_v1(embedded, observed_at, entries);
+        }
+    }
+}
+
+fn extract_v2(response: &Value, entries: &mut Vec<ScreenNameEntry>) {
+    let tweets = match response.get("data") {
+        Some(Value::Array(tweets)) => tweets.iter().collect::<Vec<_>>(),
+        Some(tweet) => vec![tweet],
+        None => vec![],
+    };
+
+    // The included users are observed at the time of the most recent tweet in the response.
+    let observed_at = tweets
+        .iter()
+        .filter_map(|tweet| parse_v2_created_at(tweet))
+        .max();
+
+    if let Some(observed_at) = observed_at {
+        let users = response
+            .get("includes")
+            .and_then(|includes| includes.get("users"))
+            .and_then(|users| users.as_array());
+
+        for user in users.into_iter().flatten() {
+            push_entry(entries, user.get("id"), user.get("username"), observed_at);
+        }
+    }
+
+    for tweet in tweets {
+        if let Some(created_at) = parse_v2_created_at(tweet) {
+            let mentions = tweet
+                .get("entities")
+                .and_then(|entities| entities.get("mentions"))
+                .and_then(|mentions| mentions.as_array());
+
+            // Mention IDs are only present in some API versions.
+            for mention in mentions.into_iter().flatten() {
+                push_entry(
+                    entries,
+                    mention.get("id"),
+                    mention.get("username"),
+                    created_at,
+                );
+            }
+        }
+    }
+}
+
+fn push_entry(
+    entries: &mut Vec<ScreenNameEntry>,
+    id: Option<&Value>,
+    screen_name: Option<&Value>,
+    timestamp: DateTime<Utc>,
+) {
+    let id = id
+        .and_then(|id| id.as_str())
+        .and_then(|id| id.parse::<u64>().ok());
+    let screen_name = screen_name.and_then(|screen_name| screen_name.as_str());
+
+    if let Some((id, screen_name)) = id.zip(screen_name) {
+        entries.push(ScreenNameEntry {
+            id,
+            screen_name: screen_name.to_string(),
+            snapshots: vec![timestamp],
+            source: None,
+        });
+    }
+}
+
+fn parse_v1_created_at(status: &Value) -> Option<DateTime<Utc>> {
+    let created_at = status.get("created_at")?.as_str()?;
+
+    DateTime::parse_from_str(created_at, V1_DATE_FORMAT)
+        .ok()
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+}
+
+fn parse_v2_created_at(tweet: &Value) -> Option<DateTime<Utc>> {
+    let created_at = tweet.get("created_at")?.as_str()?;
+
+    DateTime::parse_from_rfc3339(created_at)
+        .ok()
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn observations(value: Value) -> Vec<(u64, String, i64)> {
+        extract_entries(&value)
+            .into_iter()
+            .map(|entry| (entry.id, entry.screen_name, entry.snapshots[0].timestamp()))
+            .collect()
+    }
+
+    #[test]
+    fn retweet() {
+        let retweet = json!({
+            "created_at": "Sat Jan 02 00:00:00 +0000 2021",
+            "user": {"id_str": "1", "screen_name": "retweeter"},
+            "entities": {"user_mentions": [{"id_str": "2", "screen_name": "author"}]},
+            "retweeted_status": {
+                "created_at": "Fri Jan 01 00:00:00 +0000 2021",
+                "user": {"id_str": "2", "screen_name": "author"},
+                "in_reply_to_user_id_str": "3",
+                "in_reply_to_screen_name": "replied",
+                "entities": {"user_mentions": [{"id_str": "4", "screen_name": "mentioned"}]}
+            }
+        });
+
+        assert_eq!(
+            observations(retweet),
+            vec![
+                (1, "retweeter".to_string(), 1609545600),
+                (2, "author".to_string(), 1609545600),
+                (2, "author".to_string(), 1609545600),
+                (4, "mentioned".to_string(), 1609459200),
+                (3, "replied".to_string(), 1609459200),
+            ]
+        );
+    }
+
+    #[test]
+    fn quote() {
+        let quote = json!({
+            "created_at": "Sat Jan 02 00:00:00 +0000 2021",
+            "user": {"id_str": "1", "screen_name": "quoter"},
+            "extended_tweet": {
+                "entities": {"user_mentions": [{"id_str": "5", "screen_name": "extended"}]}
+            },
+            "entities": {"user_mentions": []},
+            "quoted_status": {
+                "created_at": "Fri Jan 01 00:00:00 +0000 2021",
+                "user": {"id_str": "2", "screen_name": "quoted"},
+                "entities": {"user_mentions": [{"id_str": "4", "screen_name": "mentioned"}]}
+            }
+        });
+
+        assert_eq!(
+            observations(quote),
+            vec![
+                (1, "quoter".to_string(), 1609545600),
+                (5, "extended".to_string(), 1609545600),
+                (2, "quoted".to_string(), 1609545600),
+                (4, "mentioned".to_string(), 1609459200),
+            ]
+        );
+    }
+
+    #[test]
+    fn deletion() {
+        let deletion = json!({
+            "delete": {
+                "status": {"id_str": "10", "user_id_str": "1"},
+                "timestamp_ms": "1609459200000"
+            }
+        });
+
+        assert!(observations(deletion).is_empty());
+    }
+
+    #[test]
+    fn v2() {
+        let response = json!({
+            "data": [
+                {
+                    "id": "10",
+                    "created_at": "2021-01-01T00:00:00.000Z",
+                    "entities": {"mentions": [{"id": "3", "username": "mentioned"}]}
+                },
+                {
+                    "id": "11",
+                    "created_at": "2021-01-02T00:00:00.000Z",
+                    "entities": {"mentions": [{"username": "without_id"}]}
+                }
+            ],
+            "includes": {
+                "users": [
+                    {"id": "1", "username": "author"},
+                    {"id": "2", "username": "other"}
+                ]
+            }
+        });
+
+        assert_eq!(
+            observations(response),
+            vec![
+                (1, "author".to_string(), 1609545600),
+                (2, "other".to_string(), 1609545600),
+                (3, "mentioned".to_string(), 1609459200),
+            ]
+        );
+    }
+}
@@ -0,0 +1,250 @@
+//! Extraction of observations from Twitter personal data exports ("Your archive" zip files).
+//!
+//! The data files are JavaScript assignments (`window.YTD.<name>.part0 = [...]`) wrapping JSON
+//! arrays. Observations are taken from:
+//!
+//! * `account.js`: the owner's current screen name, observed at the archive generation time.
+//! * `screen-name-change.js`: the old and new screen names, observed at the time of the change.
+//! * `tweet.js` / `tweets.js` (and their `-partN` continuations): mentioned and replied-to
+//!   accounts, observed at the tweet creation time.
+//!
+//! `follower.js` and `following.js` only contain account IDs, so they do not provide any pairs.
+use crate::import::{Error, Session, Sink};
+use chrono::{DateTime, NaiveDate, Utc};
+use memory_lol_import::{RejectKind, ScreenNameEntry};
+use serde_json::Value;
+use std::io::{Read, Seek};
+use zip::ZipArchive;
+
+const DATA_DIRECTORY: &str = "data/";
+const MANIFEST_FILE: &str = "data/manifest.js";
+const ACCOUNT_FILE: &str = "data/account.js";
+const SCREEN_NAME_CHANGE_FILE: &str = "data/screen-name-change.js";
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ArchiveCounts {
+    pub files: usize,
+    pub entries: usize,
+}
+
+/// Read the supported data files in an archive and add their observations to the session.
+///
+/// Data files that cannot be read or parsed are rejected (as `<name>:<file>`) according to the
+/// session's error policy, and otherwise skipped.
+pub fn load<R: Read + Seek, S: Sink>(
+    session: &mut Session<S>,
+    reader: R,
+    name: &str,
+) -> Result<ArchiveCounts, Error> {
+    session.set_input(name);
+
+    let mut zip = ZipArchive::new(reader)?;
+    let mut counts = ArchiveCounts::default();
+
+    let generated_at = match load_data_file(session, &mut zip, name, MANIFEST_FILE)? {
+        Some(manifest) => manifest
+            .pointer("/archiveInfo/generationDate")
+            .and_then(|value| value.as_str())
+            .and_then(parse_timestamp),
+        None => None,
+    }
+    .or_else(|| zip_modification_time(&mut zip, ACCOUNT_FILE));
+
+    let tweet_files = zip
+        .file_names()
+        .filter(|name| is_tweet_file(name))
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+
+    let mut add = |session: &mut Session<S>, entries: Vec<ScreenNameEntry>| {
+        counts.files += 1;
+        counts.entries += entries.len();
+
+        entries
+            .iter()
+            .try_for_each(|entry| session.add_entry(entry))
+    };
+
+    if let Some(accounts) = load_data_file(session, &mut zip, name, ACCOUNT_FILE)? {
+        match generated_at {
+            Some(generated_at) => {
+                let entries = items(&accounts, "account")
+                    .filter_map(|account| {
+                        entry(
+                            account.get("accountId"),
+                            account.get("username"),
+                            generated_at,
+                        )
+                    })
+                    .collect();
+
+                add(session, entries)?;
+            }
+            None => {
+                log::warn!(
+                    "Skipping {} (unknown archive generation time)",
+                    ACCOUNT_FILE
+                );
+            }
+        }
+    }
+
+    if let Some(changes) = load_data_file(session, &mut zip, name, SCREEN_NAME_CHANGE_FILE)? {
+        let entries = items(&changes, "screenNameChange")
+            .flat_map(|change| {
+                let id = change.get("accountId");
+                let details = change.get("screenNameChange");
+                let changed_at = details
+                    .and_then(|details| details.get("changedAt"))
+                    .and_then(|value| value.as_str())
+                    .and_then(parse_timestamp);
+
+                ["changedFrom", "changedTo"]
+                    .into_iter()
+                    .filter_map(move |field| {
+                        entry(
+                            id,
+                            details.and_then(|details| details.get(field)),
+                            changed_at?,
+                        )
+                    })
+            })
+            .collect();
+
+        add(session, entries)?;
+    }
+
+    for file in tweet_files {
+        if let Some(tweets) = load_data_file(session, &mut zip, name, &file)? {
+            let entries = items(&tweets, "tweet")
+                .flat_map(memory_lol_import::tweets::extract_entries)
+                .collect();
+
+            add(session, entries)?;
+        }
+    }
+
+    Ok(counts)
+}
+
+/// Parse a data file, returning `None` if it is not in the archive or has been rejected.
+fn load_data_file<R: Read + Seek, S: Sink>(
+    session: &mut Session<S>,
+    zip: &mut ZipArchive<R>,
+    name: &str,
+    file: &str,
+) -> Result<Option<Value>, Error> {
+    let (kind, line, detail) = match read_data_file(zip, file) {
+        Ok(value) => return Ok(value),
+        Err(Error::Json(error)) => (RejectKind::Json, error.line() as u64, error.to_string()),
+        Err(Error::Zip(error)) => (RejectKind::Record, 0, error.to_string()),
+        Err(Error::Io(error)) => (RejectKind::Record, 0, error.to_string()),
+        Err(Error::InvalidArchiveFile(_)) => (
+            RejectKind::Record,
+            0,
+            "Missing data file assignment".to_string(),
+        ),
+        Err(error) => return Err(error),
+    };
+
+    session.set_input(&format!("{name}:{file}"));
+    session.reject(kind, line, &detail, "")?;
+    session.set_input(name);
+
+    Ok(None)
+}
+
+/// Parse a data file, returning `None` if it is not in the archive.
+fn read_data_file<R: Read + Seek>(
+    zip: &mut ZipArchive<R>,
+    name: &str,
+) -> Result<Option<Value>, Error> {
+    let mut file = match zip.by_name(name) {
+        Ok(file) => file,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(error) => return Err(error.into()),
+    };
+
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+
+    // Strip the `window.YTD.<name>.part0 =` (or `window.__THAR_CONFIG =`) assignment.
+    let json = contents
+        .split_once('=')
+        .map(|(_, json)| json)
+        .ok_or_else(|| Error::InvalidArchiveFile(name.to_string()))?;
+
+    let mut values = serde_json::Deserializer::from_str(json).into_iter::<Value>();
+
+    match values.next() {
+        Some(value) => Ok(Some(value?)),
+        None => Err(Error::InvalidArchiveFile(name.to_string())),
+    }
+}
+
+/// The objects wrapped under the given field in a data file array (for example `[{"tweet": {}}]`).
+fn items<'a>(value: &'a Value, field: &'a str) -> impl Iterator<Item = &'a Value> + 'a {
+    value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(move |item| item.get(field))
+}
+
+fn entry(
+    id: Option<&Value>,
+    screen_name: Option<&Value>,
+    timestamp: DateTime<Utc>,
+) -> Option<ScreenNameEntry> {
+    let id = id?.as_str()?.parse::<u64>().ok()?;
+    let screen_name = screen_name?.as_str()?.to_string();
+
+    Some(ScreenNameEntry {
+        id,
+        screen_name,
+        snapshots: vec![timestamp],
+        source: None,
+    })
+}
+
+fn is_tweet_file(name: &str) -> bool {
+    name.strip_prefix(DATA_DIRECTORY)
+        .and_then(|name| name.strip_suffix(".js"))
+        .and_then(|name| {
+            name.strip_prefix("tweets")
+                .or_else(|| name.strip_prefix("tweet"))
+        })
+        .filter(|suffix| {
+            suffix.is_empty()
+                || suffix
+                    .strip_prefix("-part")
+                    .filter(|part| part.chars().all(|ch| ch.is_ascii_digit()))
+                    .is_some()
+        })
+        .is_some()
+}
+
+fn parse_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+}
+
+fn zip_modification_time<R: Read + Seek>(
+    zip: &mut ZipArchive<R>,
+    name: &str,
+) -> Option<DateTime<Utc>> {
+    let modified = zip.by_name(name).ok()?.last_modified()?;
+
+    NaiveDate::from_ymd_opt(
+        modified.year().into(),
+        modified.month().into(),
+        modified.day().into(),
+    )?
+    .and_hms_opt(
+        modified.hour().into(),
+        modified.minute().into(),
+        modified.second().into(),
+    )
+    .map(|timestamp| timestamp.and_utc())
+}
@@ -0,0 +1,264 @@
+//! Resumable, parallel import of Twitter Stream Grab output directories.
+//!
+//! Directories are parsed by worker threads, which send aggregated observations to a single writer
+//! (the calling thread). Each directory is recorded in the database's import ledger once all of
+//! its observations have been written, and directories whose files have already been imported are
+//! skipped. Files whose size and modification time match the ledger are assumed to be unchanged,
+//! and only other files are hashed to check whether their contents have changed.
+//!
+//! The inputs in each directory are selected by name (`names.csv` and `profiles.ndjson`, either of
+//! which may have a compression extension, in which case the compressed file is preferred), and
+//! their compression is detected from their contents.
+use crate::import::{Batch, Error, Session, Sink, UpdateMode};
+use crate::input;
+use crate::rejects::{RejectCounts, Rejects};
+use chrono::{DateTime, Utc};
+use memory_lol::db::{
+    accounts::MergePolicy,
+    metadata::{ImportRecord, ImportedFile},
+    Database,
+};
+use memory_lol_import::{Importer, Registry};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender};
+
+/// Input file names and the names of their formats in the registry.
+const INPUT_FILES: [(&str, &str); 2] = [("names.csv", "mentions"), ("profiles.ndjson", "profiles")];
+
+/// Compression extensions in order of preference (the last is an uncompressed file).
+const COMPRESSION_EXTENSIONS: [&str; 5] = [".zst", ".gz", ".bz2", ".xz", ""];
+
+#[derive(Clone, Copy, Debug)]
+pub struct BatchOptions {
+    pub workers: usize,
+    pub memory_limit: Option<usize>,
+    /// Import directories even if the ledger shows that their files have already been imported.
+    pub force: bool,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BatchCounts {
+    pub imported: usize,
+    pub skipped: usize,
+    pub entries: u64,
+    pub rejected: RejectCounts,
+}
+
+enum Message {
+    Batch(Batch),
+    Done {
+        record: ImportRecord,
+        rejected: RejectCounts,
+    },
+    Skipped(String),
+}
+
+struct ChannelSink<'a> {
+    tx: &'a SyncSender<Result<Message, Error>>,
+    merge_policy: MergePolicy,
+}
+
+impl Sink for ChannelSink<'_> {
+    fn write(&mut self, batch: Batch) -> Result<(), Error> {
+        self.tx
+            .send(Ok(Message::Batch(batch)))
+            .map_err(|_| Error::WriterStopped)
+    }
+
+    fn merge_policy(&self) -> Result<MergePolicy, Error> {
+        Ok(self.merge_policy)
+    }
+}
+
+/// Import the given directories, writing to the sink on the calling thread.
+///
+/// The database is only used to read the import ledger.
+pub fn import_batch<M, S: Sink>(
+    db: &Database<M>,
+    mut sink: S,
+    directories: &[PathBuf],
+    options: &BatchOptions,
+    rejects: &Rejects,
+) -> Result<BatchCounts, Error> {
+    let ledger = db
+        .metadata
+        .get_import_records()?
+        .into_iter()
+        .map(|record| (record.path.clone(), record))
+        .collect::<HashMap<_, _>>();
+    let merge_policy = sink.merge_policy()?;
+    let registry = input::registry();
+    let next = AtomicUsize::new(0);
+    let workers = options.workers.max(1);
+
+    std::thread::scope(|scope| {
+        let (tx, rx) = sync_channel(workers * 2);
+
+        for _ in 0..workers {
+            let tx = tx.clone();
+            let registry = &registry;
+            let ledger = &ledger;
+            let next = &next;
+
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+
+                match directories.get(index) {
+                    Some(directory) => {
+                        let message = import_directory(
+                            directory,
+                            registry,
+                            ledger,
+                            options,
+                            rejects,
+                            &tx,
+                            merge_policy,
+                        );
+                        let failed = message.is_err();
+
+                        if tx.send(message).is_err() || failed {
+                            break;
+                        }
+                    }
+                    None => break,
+                }
+            });
+        }
+
+        drop(tx);
+
+        let mut counts = BatchCounts::default();
+
+        for message in rx {
+            match message? {
+                Message::Batch(batch) => sink.write(batch)?,
+                Message::Done { record, rejected } => {
+                    sink.record_import(&record)?;
+                    counts.imported += 1;
+                    counts.entries += record.entries;
+                    counts.rejected.merge(&rejected);
+
+                    log::info!(
+                        "[{}/{}] Imported {} ({} entries)",
+                        counts.imported + counts.skipped,
+                        directories.len(),
+                        record.path,
+                        record.entries
+                    );
+                }
+                Message::Skipped(path) => {
+                    counts.skipped += 1;
+
+                    log::info!(
+                        "[{}/{}] Skipped {} (already imported)",
+                        counts.imported + counts.skipped,
+                        directories.len(),
+                        path
+                    );
+                }
+            }
+        }
+
+        Ok(counts)
+    })
+}
+
+fn import_directory(
+    directory: &Path,
+    registry: &Registry,
+    ledger: &HashMap<String, ImportRecord>,
+    options: &BatchOptions,
+    rejects: &Rejects,
+    tx: &SyncSender<Result<Message, Error>>,
+    merge_policy: MergePolicy,
+) -> Result<Message, Error> {
+    let path = std::fs::canonicalize(directory)
+        .unwrap_or_else(|_| directory.to_path_buf())
+        .to_string_lossy()
+        .to_string();
+
+    let inputs = directory_inputs(directory, registry);
+
+    let mut files = inputs
+        .iter()
+        .map(|(path, _)| {
+            let metadata = std::fs::metadata(path)?;
+
+            Ok(ImportedFile {
+                name: path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                sha256: String::new(),
+                entries: 0,
+                size: Some(metadata.len()),
+                modified: metadata.modified().ok().map(DateTime::<Utc>::from),
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let record = ledger.get(&path).filter(|_| !options.force);
+
+    if record.is_some_and(|record| record.has_same_file_metadata(&files)) {
+        return Ok(Message::Skipped(path));
+    }
+
+    for ((input_path, _), file) in inputs.iter().zip(files.iter_mut()) {
+        file.sha256 = input::hash_file(input_path)?;
+    }
+
+    if record.is_some_and(|record| record.has_same_files(&files)) {
+        return Ok(Message::Skipped(path));
+    }
+
+    let mut rejected = RejectCounts::default();
+
+    for ((input_path, importer), file) in inputs.into_iter().zip(files.iter_mut()) {
+        let input_path = input_path.to_string_lossy();
+        let source = input::open(&input_path)?;
+
+        let mut session = Session::new(ChannelSink { tx, merge_policy }, UpdateMode::Range)
+            .with_memory_limit(options.memory_limit)?
+            .with_rejects(rejects.for_input(&input_path));
+
+        session.load(importer, source)?;
+
+        let session_counts = session.finish()?;
+        file.entries = session_counts.entries as u64;
+        rejected.merge(&session_counts.rejected);
+    }
+
+    Ok(Message::Done {
+        record: ImportRecord {
+            path,
+            entries: files.iter().map(|file| file.entries).sum(),
+            files,
+            imported_at: Utc::now(),
+            batch: None,
+        },
+        rejected,
+    })
+}
+
+/// The input files in a directory (at most one for each input file name) and their formats.
+fn directory_inputs<'a>(
+    directory: &Path,
+    registry: &'a Registry,
+) -> Vec<(PathBuf, &'a dyn Importer)> {
+    let mut inputs = vec![];
+
+    for (name, format) in INPUT_FILES {
+        let path = COMPRESSION_EXTENSIONS
+            .iter()
+            .map(|extension| directory.join(format!("{name}{extension}")))
+            .find(|path| path.is_file());
+
+        if let Some((path, importer)) = path.zip(registry.get(format)) {
+            inputs.push((path, importer));
+        }
+    }
+
+    inputs
+}
@@ -0,0 +1,123 @@
+//! Comparison of import input against an existing database without writing anything.
+use crate::import::{Batch, Error, Sink};
+use crate::rejects::RejectCounts;
+use memory_lol::db::{
+    accounts::{is_storable_date, MergePolicy},
+    Database,
+};
+use memory_lol_import::RejectKind;
+use std::collections::HashSet;
+
+/// What an import would do to the database.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct DryRunReport {
+    /// Pairs read from the input (pairs read in several chunks are counted once per chunk).
+    pub pairs: usize,
+    pub new_accounts: usize,
+    pub new_pairs: usize,
+    /// Existing pairs whose first or last observation date would change.
+    pub range_extensions: usize,
+    pub invalid_screen_names: usize,
+    /// Observation dates that cannot be stored, which the import would reject.
+    pub out_of_range_dates: usize,
+    pub unparsable: usize,
+}
+
+impl DryRunReport {
+    pub fn print(&self) {
+        println!("Pairs read: {}", self.pairs);
+        println!("New accounts: {}", self.new_accounts);
+        println!("New pairs: {}", self.new_pairs);
+        println!("Range extensions: {}", self.range_extensions);
+        println!("Invalid screen names: {}", self.invalid_screen_names);
+        println!("Out-of-range dates: {}", self.out_of_range_dates);
+        println!("Unparsable input: {}", self.unparsable);
+    }
+}
+
+/// A sink that compares each batch against the database instead of writing it.
+///
+/// Pairs that appear in several chunks are tracked so that they are only reported once.
+pub struct DryRun<'a, M> {
+    db: &'a Database<M>,
+    new_accounts: HashSet<u64>,
+    new_pairs: HashSet<(u64, String)>,
+    extended_pairs: HashSet<(u64, String)>,
+    report: DryRunReport,
+}
+
+impl<'a, M> DryRun<'a, M> {
+    pub fn new(db: &'a Database<M>) -> Self {
+        Self {
+            db,
+            new_accounts: HashSet::new(),
+            new_pairs: HashSet::new(),
+            extended_pairs: HashSet::new(),
+            report: DryRunReport::default(),
+        }
+    }
+
+    pub fn finish(self, rejected: &RejectCounts) -> DryRunReport {
+        DryRunReport {
+            new_accounts: self.new_accounts.len(),
+            new_pairs: self.new_pairs.len(),
+            range_extensions: self.extended_pairs.len(),
+            invalid_screen_names: rejected.get(RejectKind::ScreenName),
+            unparsable: rejected.total() - rejected.get(RejectKind::ScreenName),
+            ..self.report
+        }
+    }
+}
+
+impl<M: Sync + Send + 'static> Sink for DryRun<'_, M> {
+    fn merge_policy(&self) -> Result<MergePolicy, Error> {
+        Ok(self.db.metadata.get_merge_policy()?)
+    }
+
+    fn write(&mut self, batch: Batch) -> Result<(), Error> {
+        for (id, screen_name, observations) in batch {
+            self.report.pairs += 1;
+
+            let out_of_range_dates = observations
+                .dates
+                .iter()
+                .filter(|date| !is_storable_date(date))
+                .count();
+
+            if out_of_range_dates > 0 {
+                self.report.out_of_range_dates += out_of_range_dates;
+                continue;
+            }
+
+            let existing = self.db.lookup_by_user_id(id)?;
+
+            if existing.is_empty() {
+                self.new_accounts.insert(id);
+            }
+
+            match existing.get(&screen_name) {
+                None => {
+                    self.new_pairs.insert((id, screen_name));
+                }
+                Some(existing) => {
+                    let range = observations.dates.first().zip(observations.dates.last());
+                    let existing_range = existing.dates.first().zip(existing.dates.last());
+
+                    let extends = match (range, existing_range) {
+                        (Some((first, last)), Some((existing_first, existing_last))) => {
+                            first < existing_first || last > existing_last
+                        }
+                        (Some(_), None) => true,
+                        (None, _) => false,
+                    };
+
+                    if extends {
+                        self.extended_pairs.insert((id, screen_name));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+use arrow_array::{
+    builder::{Date32Builder, ListBuilder, StringBuilder, UInt64Builder},
+    ArrayRef, RecordBatch,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use chrono::NaiveDate;
+use memory_lol::db::accounts::{Observations, PairIterator};
+use parquet::{
+    arrow::ArrowWriter,
+    basic::{Compression, ZstdLevel},
+    file::properties::WriterProperties,
+};
+use std::io::Write;
+use std::sync::Arc;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Arrow error")]
+    Arrow(#[from] arrow_schema::ArrowError),
+    #[error("Parquet error")]
+    Parquet(#[from] parquet::errors::ParquetError),
+    #[error("Database error")]
+    Db(#[from] memory_lol::db::Error),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// Apache Parquet
+    Parquet,
+    /// Arrow IPC file format
+    Arrow,
+}
+
+/// Columns are `id`, `screen_name`, and the first and last observation dates (`first_seen` and
+/// `last_seen`), with an optional `dates` list column containing every observation date.
+pub fn schema(include_dates: bool) -> SchemaRef {
+    let mut fields = vec![
+        Field::new("id", DataType::UInt64, false),
+        Field::new("screen_name", DataType::Utf8, false),
+        Field::new("first_seen", DataType::Date32, true),
+        Field::new("last_seen", DataType::Date32, true),
+    ];
+
+    if include_dates {
+        fields.push(Field::new(
+            "dates",
+            DataType::List(Arc::new(Field::new_list_field(DataType::Date32, true))),
+            false,
+        ));
+    }
+
+    Arc::new(Schema::new(fields))
+}
+
+/// Write pairs in batches of the given size (which is also used as the Parquet row group size),
+/// returning the number of rows written.
+pub fn export<W: Write + Send>(
+    pairs: PairIterator<'_>,
+    writer: W,
+    format: ExportFormat,
+    include_dates: bool,
+    batch_size: usize,
+) -> Result<usize, Error> {
+    let schema = schema(include_dates);
+
+    let mut writer = match format {
+        ExportFormat::Parquet => {
+            let properties = WriterProperties::builder()
+                .set_compression(Compression::ZSTD(ZstdLevel::default()))
+                .set_max_row_group_size(batch_size)
+                .build();
+
+            BatchWriter::Parquet(ArrowWriter::try_new(
+                writer,
+                schema.clone(),
+                Some(properties),
+            )?)
+        }
+        ExportFormat::Arrow => {
+            BatchWriter::Arrow(arrow_ipc::writer::FileWriter::try_new(writer, &schema)?)
+        }
+    };
+
+    let mut batch = PairBatchBuilder::new(include_dates, batch_size);
+    let mut count = 0;
+
+    for pair in pairs {
+        let (id, screen_name, observations) = pair?;
+        batch.push(id, &screen_name, &observations);
+
+        if batch.len() >= batch_size {
+            count += batch.len();
+            writer.write(&batch.finish(&schema)?)?;
+        }
+    }
+
+    if batch.len() > 0 {
+        count += batch.len();
+        writer.write(&batch.finish(&schema)?)?;
+    }
+
+    writer.close()?;
+
+    Ok(count)
+}
+
+enum BatchWriter<W: Write + Send> {
+    Parquet(ArrowWriter<W>),
+    Arrow(arrow_ipc::writer::FileWriter<W>),
+}
+
+impl<W: Write + Send> BatchWriter<W> {
+    fn write(&mut self, batch: &RecordBatch) -> Result<(), Error> {
+        match self {
+            Self::Parquet(writer) => Ok(writer.write(batch)?),
+            Self::Arrow(writer) => Ok(writer.write(batch)?),
+        }
+    }
+
+    fn close(self) -> Result<(), Error> {
+        match self {
+            Self::Parquet(writer) => {
+                writer.close()?;
+            }
+            Self::Arrow(mut writer) => {
+                writer.finish()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct PairBatchBuilder {
+    len: usize,
+    ids: UInt64Builder,
+    screen_names: StringBuilder,
+    first_seen: Date32Builder,
+    last_seen: Date32Builder,
+    dates: Option<ListBuilder<Date32Builder>>,
+}
+
+impl PairBatchBuilder {
+    fn new(include_dates: bool, capacity: usize) -> Self {
+        Self {
+            len: 0,
+            ids: UInt64Builder::with_capacity(capacity),
+            screen_names: StringBuilder::new(),
+            first_seen: Date32Builder::with_capacity(capacity),
+            last_seen: Date32Builder::with_capacity(capacity),
+            dates: if include_dates {
+                Some(ListBuilder::new(Date32Builder::new()))
+            } else {
+                None
+            },
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn push(&mut self, id: u64, screen_name: &str, observations: &Observations) {
+        self.ids.append_value(id);
+        self.screen_names.append_value(screen_name);
+        self.first_seen
+            .append_option(observations.dates.first().map(date_to_days));
+        self.last_seen
+            .append_option(observations.dates.last().map(date_to_days));
+
+        if let Some(dates) = self.dates.as_mut() {
+            for date in &observations.dates {
+                dates.values().append_value(date_to_days(date));
+            }
+
+            dates.append(true);
+        }
+
+        self.len += 1;
+    }
+
+    /// Build a record batch from the buffered rows and reset the builder.
+    fn finish(&mut self, schema: &SchemaRef) -> Result<RecordBatch, Error> {
+        let mut columns: Vec<ArrayRef> = vec![
+            Arc::new(self.ids.finish()),
+            Arc::new(self.screen_names.finish()),
+            Arc::new(self.first_seen.finish()),
+            Arc::new(self.last_seen.finish()),
+        ];
+
+        if let Some(dates) = self.dates.as_mut() {
+            columns.push(Arc::new(dates.finish()));
+        }
+
+        self.len = 0;
+
+        Ok(RecordBatch::try_new(schema.clone(), columns)?)
+    }
+}
+
+/// Arrow's `Date32` is the number of days since the Unix epoch.
+fn date_to_days(date: &NaiveDate) -> i32 {
+    (*date - NaiveDate::default()).num_days() as i32
+}
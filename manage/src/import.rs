@@ -1,8 +1,13 @@
-use chrono::{DateTime, NaiveDate, TimeZone, Utc};
-use memory_lol::db::{table::Writeable, Database};
-use serde_json::Value;
+use crate::rejects::{RejectCounts, Rejects};
+use chrono::{DateTime, NaiveDate, Utc};
+use memory_lol::db::{
+    accounts::{MergePolicy, Observations},
+    metadata::ImportRecord,
+    util::is_valid_screen_name,
+};
+use memory_lol_import::{Importer, RejectKind, ScreenNameEntry};
 use std::collections::HashMap;
-use std::io::{BufRead, Read};
+use std::io::{BufRead, Write};
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -12,164 +17,304 @@ pub enum Error {
     Json(#[from] serde_json::Error),
     #[error("CSV error")]
     Csv(#[from] csv::Error),
-    #[error("CSV record encoding error")]
-    InvalidCsvRecord(csv::StringRecord),
     #[error("Database error")]
     Db(#[from] memory_lol::db::Error),
+    #[error("Input format error")]
+    Format(#[from] memory_lol_import::Error),
+    #[error("Import writer stopped")]
+    WriterStopped,
+    #[error("Invalid WARC record")]
+    InvalidWarcRecord(String),
+    #[error("Zip error")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("Invalid archive data file")]
+    InvalidArchiveFile(String),
+    #[error("Rejected input line")]
+    Rejected {
+        input: String,
+        line: u64,
+        kind: RejectKind,
+        detail: String,
+    },
+    #[error("Rejects file unavailable")]
+    RejectsUnavailable,
+    #[error("A memory limit requires the range merge policy (see set-merge-policy)")]
+    MemoryLimitWithoutRangePolicy,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct ScreenNameEntry {
-    pub id: u64,
-    pub screen_name: String,
-    pub snapshots: Vec<DateTime<Utc>>,
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UpdateMode {
+    All,
+    Range,
+}
+
+/// Rough per-pair overhead of a session entry (the key, the snapshot vector, and hash map slot).
+const ENTRY_OVERHEAD_BYTES: usize = 80;
+
+/// Aggregated observations for ID-screen name pairs.
+pub type Batch = Vec<(u64, String, Observations)>;
+
+/// Receives the aggregated observations from a session.
+pub trait Sink {
+    fn write(&mut self, batch: Batch) -> Result<(), Error>;
+
+    /// The merge policy of the database that the observations are written to.
+    fn merge_policy(&self) -> Result<MergePolicy, Error>;
+
+    /// Record a completed import in the import ledger (by default nothing is recorded).
+    fn record_import(&mut self, _record: &ImportRecord) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
-impl ScreenNameEntry {
-    pub fn from_record(record: &csv::StringRecord) -> Result<Self, Error> {
-        Self::from_record_opt(record).ok_or_else(|| Error::InvalidCsvRecord(record.clone()))
+impl<S: Sink + ?Sized> Sink for &mut S {
+    fn write(&mut self, batch: Batch) -> Result<(), Error> {
+        (**self).write(batch)
     }
 
-    pub fn from_json(value: &Value) -> Option<Self> {
-        let id_str_value = value.get("id_str")?;
-        let id_str_string = id_str_value.as_str()?;
-        let id = id_str_string.parse::<u64>().ok()?;
-        let screen_name_value = value.get("screen_name")?;
-        let screen_name = screen_name_value.as_str()?.to_string();
-        let snapshot_value = value.get("snapshot")?;
-        let snapshot = Utc.timestamp_opt(snapshot_value.as_i64()?, 0).single()?;
-        let snapshots = vec![snapshot];
-
-        Some(Self {
-            id,
-            screen_name,
-            snapshots,
-        })
+    fn merge_policy(&self) -> Result<MergePolicy, Error> {
+        (**self).merge_policy()
     }
 
-    pub fn from_record_opt(record: &csv::StringRecord) -> Option<Self> {
-        let id = record.get(0).and_then(|value| value.parse::<u64>().ok())?;
-        let screen_name = record.get(1)?.to_string();
-        let snapshot_value = record.get(2).and_then(|value| value.parse::<i64>().ok())?;
-        let snapshot = Utc.timestamp_opt(snapshot_value, 0).single()?;
-        let snapshots = vec![snapshot];
-
-        Some(Self {
-            id,
-            screen_name,
-            snapshots,
-        })
+    fn record_import(&mut self, record: &ImportRecord) -> Result<(), Error> {
+        (**self).record_import(record)
     }
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub enum UpdateMode {
-    All,
-    Range,
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SessionCounts {
+    /// Entries written by the session (pairs that were written in several chunks are counted once
+    /// per chunk).
+    pub entries: usize,
+    /// Input lines that could not be parsed (and were not aborted on).
+    pub rejected: RejectCounts,
 }
 
-#[derive(Default)]
-pub struct Session {
+/// Aggregates observations for ID-screen name pairs and writes them to a sink.
+///
+/// Without a memory limit everything is written when the session is finished. With a limit, the
+/// aggregated entries are written in chunks whenever their estimated size exceeds it, and the
+/// merge operator combines the observations from different chunks. In range mode this requires the
+/// range merge policy, since with the `all` policy a pair that appears in several chunks would keep
+/// the first and last date from each chunk.
+pub struct Session<S> {
+    sink: S,
+    mode: UpdateMode,
+    memory_limit: Option<usize>,
+    source_log: Option<csv::Writer<Box<dyn Write>>>,
     data: HashMap<(u64, String), Vec<DateTime<Utc>>>,
+    estimated_bytes: usize,
+    rejects: Rejects,
+    counts: SessionCounts,
 }
 
-impl Session {
-    pub fn load_json<R: BufRead>(source: R) -> Result<Self, Error> {
-        let mut session = Session::default();
-
-        for line in source.lines() {
-            let line = line?;
-            match serde_json::from_str(&line) {
-                Ok(value) => {
-                    if let Some(entry) = ScreenNameEntry::from_json(&value) {
-                        session.add_entry(&entry);
-                    }
-                }
-                Err(error) => {
-                    log::warn!("JSON error: {}", error);
-                }
-            }
+impl<S: Sink> Session<S> {
+    pub fn new(sink: S, mode: UpdateMode) -> Self {
+        Self {
+            sink,
+            mode,
+            memory_limit: None,
+            source_log: None,
+            data: HashMap::new(),
+            estimated_bytes: 0,
+            rejects: Rejects::default(),
+            counts: SessionCounts::default(),
         }
+    }
 
-        Ok(session)
+    /// Set how lines that cannot be parsed are handled (by default they are skipped).
+    pub fn with_rejects(mut self, rejects: Rejects) -> Self {
+        self.rejects = rejects;
+        self
     }
 
-    pub fn load_mentions<R: Read>(source: R) -> Result<Self, Error> {
-        let mut session = Session::default();
-        let mut reader = csv::ReaderBuilder::new()
-            .has_headers(false)
-            .from_reader(source);
+    /// Switch to a new input, so that rejected lines are reported against it.
+    pub fn set_input(&mut self, input: &str) {
+        let rejects = self.rejects.for_input(input);
+        let previous = std::mem::replace(&mut self.rejects, rejects);
+        self.counts.rejected.merge(previous.counts());
+    }
 
-        for record in reader.records() {
-            let record = record?;
-            let entry = ScreenNameEntry::from_record(&record)?;
-            session.add_entry(&entry);
+    /// Set the approximate number of bytes of aggregated entries to hold before writing.
+    ///
+    /// Fails in range mode if the sink's database doesn't use the range merge policy.
+    pub fn with_memory_limit(mut self, memory_limit: Option<usize>) -> Result<Self, Error> {
+        if memory_limit.is_some()
+            && self.mode == UpdateMode::Range
+            && self.sink.merge_policy()? != MergePolicy::Range
+        {
+            return Err(Error::MemoryLimitWithoutRangePolicy);
         }
 
-        Ok(session)
+        self.memory_limit = memory_limit;
+        Ok(self)
     }
 
-    pub fn add_entry(&mut self, entry: &ScreenNameEntry) {
-        let snapshots = self
-            .data
-            .entry((entry.id, entry.screen_name.to_string()))
-            .or_default();
-        snapshots.extend(&entry.snapshots);
+    /// Write a CSV row (user ID, screen name, epoch second, source) for each observation that has a
+    /// source.
+    pub fn with_source_log(mut self, source_log: Box<dyn Write>) -> Self {
+        self.source_log = Some(
+            csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(source_log),
+        );
+        self
     }
 
-    fn insert(
-        db: &Database<Writeable>,
-        id: u64,
-        screen_name: &str,
-        dates: Vec<NaiveDate>,
+    /// Handle an input line (or record) that cannot be imported according to the session's error
+    /// policy.
+    pub fn reject(
+        &mut self,
+        kind: RejectKind,
+        line: u64,
+        detail: &str,
+        contents: &str,
     ) -> Result<(), Error> {
-        match db.insert(id, screen_name, dates) {
-            Err(memory_lol::db::Error::InvalidScreenName(screen_name)) => {
-                log::warn!("Invalid screen name: {}", screen_name);
-                Ok(())
+        self.rejects.reject(kind, line, detail, contents)
+    }
+
+    /// Add the entries read by an importer, handling lines that cannot be imported according to the
+    /// session's error policy.
+    pub fn load<'a>(
+        &mut self,
+        importer: &dyn Importer,
+        source: Box<dyn BufRead + 'a>,
+    ) -> Result<(), Error> {
+        for entry in importer.entries(source) {
+            match entry {
+                Ok(entry) => self.add_entry(&entry)?,
+                Err(error) => self.rejects.handle(error)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add an entry, rejecting it if its screen name is invalid (entries don't have line numbers,
+    /// so these rejects are reported as line 0).
+    pub fn add_entry(&mut self, entry: &ScreenNameEntry) -> Result<(), Error> {
+        if !is_valid_screen_name(&entry.screen_name) {
+            return self.rejects.reject(
+                RejectKind::ScreenName,
+                0,
+                &format!("Invalid screen name: {}", entry.screen_name),
+                &format!("{},{}", entry.id, entry.screen_name),
+            );
+        }
+
+        if let Some((source_log, source)) = self.source_log.as_mut().zip(entry.source.as_ref()) {
+            for snapshot in &entry.snapshots {
+                source_log.write_record([
+                    entry.id.to_string().as_str(),
+                    &entry.screen_name,
+                    snapshot.timestamp().to_string().as_str(),
+                    source,
+                ])?;
             }
-            other => Ok(other?),
         }
+
+        let key = (entry.id, entry.screen_name.to_string());
+
+        if !self.data.contains_key(&key) {
+            self.estimated_bytes += ENTRY_OVERHEAD_BYTES + entry.screen_name.len();
+        }
+
+        let snapshots = self.data.entry(key).or_default();
+        let previous_len = snapshots.len();
+        snapshots.extend(&entry.snapshots);
+
+        // Only the first and last times are needed to compute a range.
+        if self.mode == UpdateMode::Range && snapshots.len() > 2 {
+            let first = snapshots.iter().min().copied();
+            let last = snapshots.iter().max().copied();
+            snapshots.clear();
+            snapshots.extend(first.into_iter().chain(last));
+        }
+
+        self.estimated_bytes += snapshots.len() * std::mem::size_of::<DateTime<Utc>>();
+        self.estimated_bytes -= previous_len * std::mem::size_of::<DateTime<Utc>>();
+
+        if self
+            .memory_limit
+            .filter(|memory_limit| self.estimated_bytes > *memory_limit)
+            .is_some()
+        {
+            self.flush()?;
+        }
+
+        Ok(())
     }
 
-    pub fn update(&self, db: &Database<Writeable>, mode: UpdateMode) -> Result<usize, Error> {
-        let mut count = 0;
+    /// Write the aggregated entries to the sink.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if !self.data.is_empty() {
+            log::debug!(
+                "Writing {} entries (approximately {} bytes)",
+                self.data.len(),
+                self.estimated_bytes
+            );
 
-        for ((id, screen_name), snapshots) in &self.data {
-            let mut dates = to_dates(snapshots);
-            dates.sort();
-            dates.dedup();
+            let batch = self
+                .data
+                .drain()
+                .map(|((id, screen_name), snapshots)| {
+                    (id, screen_name, to_observations(&snapshots, self.mode))
+                })
+                .collect::<Vec<_>>();
 
-            match mode {
-                UpdateMode::All => {
-                    Self::insert(db, *id, screen_name, dates)?;
-                }
+            self.counts.entries += batch.len();
+            self.estimated_bytes = 0;
+            self.sink.write(batch)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write any remaining entries.
+    pub fn finish(mut self) -> Result<SessionCounts, Error> {
+        self.flush()?;
+        self.rejects.flush()?;
+        self.counts.rejected.merge(self.rejects.counts());
+
+        if let Some(source_log) = self.source_log.as_mut() {
+            source_log.flush()?;
+        }
 
-                UpdateMode::Range => {
-                    let range = if dates.len() <= 2 {
-                        dates
-                    } else {
-                        let mut range = Vec::with_capacity(2);
+        Ok(self.counts)
+    }
+}
 
-                        if let Some(first) = dates.first() {
-                            range.push(*first);
-                        }
-                        if let Some(last) = dates.last() {
-                            range.push(*last);
-                        }
+/// Convert observation times into the dates to be stored (either all of them or only the first
+/// and last) together with the exact first and last times.
+pub fn to_observations(timestamps: &[DateTime<Utc>], mode: UpdateMode) -> Observations {
+    let mut dates = to_dates(timestamps);
+    dates.sort();
+    dates.dedup();
 
-                        range
-                    };
+    let dates = match mode {
+        UpdateMode::All => dates,
+        UpdateMode::Range => {
+            if dates.len() <= 2 {
+                dates
+            } else {
+                let mut range = Vec::with_capacity(2);
 
-                    Self::insert(db, *id, screen_name, range)?;
+                if let Some(first) = dates.first() {
+                    range.push(*first);
+                }
+                if let Some(last) = dates.last() {
+                    range.push(*last);
                 }
-            }
 
-            count += 1;
+                range
+            }
         }
+    };
 
-        Ok(count)
-    }
+    let first = timestamps.iter().min();
+    let last = timestamps.iter().max();
+
+    Observations::new(dates, first.zip(last).map(|(first, last)| (*first, *last)))
 }
 
 fn to_dates(timestamps: &[DateTime<Utc>]) -> Vec<NaiveDate> {
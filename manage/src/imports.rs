@@ -0,0 +1,152 @@
+//! The import audit log: every import run is recorded as an import batch in the database metadata.
+//!
+//! A batch records the command, operator, inputs (with file hashes), timestamps, and counts, and
+//! optionally the dates written for every pair, which allows the batch to be rolled back.
+use crate::import::{Batch, Error, Sink};
+use chrono::Utc;
+use memory_lol::db::{
+    accounts::MergePolicy,
+    metadata::{BatchInput, ImportBatch, ImportRecord},
+    table::Writeable,
+    Database,
+};
+use std::path::Path;
+
+/// Writes observations to the database, recording the pairs and ledger entries for a batch.
+pub struct BatchSink<'a> {
+    db: &'a Database<Writeable>,
+    batch: u64,
+    record_pairs: bool,
+}
+
+impl<'a> BatchSink<'a> {
+    pub fn new(db: &'a Database<Writeable>, batch: &ImportBatch) -> Self {
+        Self {
+            db,
+            batch: batch.id,
+            record_pairs: batch.pairs_recorded,
+        }
+    }
+}
+
+impl Sink for BatchSink<'_> {
+    fn merge_policy(&self) -> Result<MergePolicy, Error> {
+        Ok(self.db.get_merge_policy()?)
+    }
+
+    fn write(&mut self, batch: Batch) -> Result<(), Error> {
+        for (id, screen_name, observations) in batch {
+            if self.record_pairs {
+                self.db
+                    .insert_batch_observations(self.batch, id, &screen_name, &observations)?;
+            } else {
+                self.db
+                    .insert_observations(id, &screen_name, &observations)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn record_import(&mut self, record: &ImportRecord) -> Result<(), Error> {
+        let mut record = record.clone();
+        record.batch = Some(self.batch);
+
+        Ok(self.db.metadata.put_import_record(&record)?)
+    }
+}
+
+/// Create and store a new (unfinished) import batch.
+///
+/// Files are hashed and recorded with their canonical paths, while directories and standard input
+/// are recorded as given.
+pub fn start(
+    db: &Database<Writeable>,
+    command: &str,
+    inputs: &[String],
+    operator: Option<String>,
+    record_pairs: bool,
+) -> Result<ImportBatch, Error> {
+    let inputs = inputs
+        .iter()
+        .map(|input| {
+            let path = Path::new(input);
+
+            Ok(if path.is_file() {
+                BatchInput {
+                    path: std::fs::canonicalize(path)?.to_string_lossy().to_string(),
+                    sha256: Some(crate::input::hash_file(path)?),
+                }
+            } else {
+                BatchInput {
+                    path: input.clone(),
+                    sha256: None,
+                }
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let batch = ImportBatch {
+        id: db.metadata.next_import_batch_id()?,
+        command: command.to_string(),
+        operator,
+        inputs,
+        started_at: Utc::now(),
+        finished_at: None,
+        entries: 0,
+        rejected: 0,
+        pairs_recorded: record_pairs,
+        rolled_back_at: None,
+    };
+
+    db.metadata.put_import_batch(&batch)?;
+
+    Ok(batch)
+}
+
+/// Mark a batch as finished with the given counts.
+pub fn finish(
+    db: &Database<Writeable>,
+    mut batch: ImportBatch,
+    entries: u64,
+    rejected: u64,
+) -> Result<ImportBatch, Error> {
+    batch.finished_at = Some(Utc::now());
+    batch.entries = entries;
+    batch.rejected = rejected;
+
+    db.metadata.put_import_batch(&batch)?;
+
+    Ok(batch)
+}
+
+pub fn print(batch: &ImportBatch) {
+    let status = match (batch.finished_at, batch.rolled_back_at) {
+        (_, Some(rolled_back_at)) => format!("rolled back {}", rolled_back_at.to_rfc3339()),
+        (Some(finished_at), None) => format!("finished {}", finished_at.to_rfc3339()),
+        (None, None) => "not finished".to_string(),
+    };
+
+    println!(
+        "{}: {} by {}, started {}, {} ({} entries, {} rejected{})",
+        batch.id,
+        batch.command,
+        batch.operator.as_deref().unwrap_or("unknown"),
+        batch.started_at.to_rfc3339(),
+        status,
+        batch.entries,
+        batch.rejected,
+        if batch.pairs_recorded {
+            ", pairs recorded"
+        } else {
+            ""
+        }
+    );
+
+    for input in &batch.inputs {
+        match &input.sha256 {
+            Some(sha256) => println!("    {} ({})", input.path, sha256),
+            None => println!("    {}", input.path),
+        }
+    }
+}
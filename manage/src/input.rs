@@ -0,0 +1,196 @@
+//! Opening import inputs, with automatic detection of compression and format.
+//!
+//! Compression is detected from magic bytes (zstd, gzip, bzip2, and xz are supported), and the
+//! format is detected from the first line of the decompressed content by the formats in the
+//! registry (see [`registry`]). Tar files (identified by the `ustar` magic in the first header) are
+//! assumed to be Twitter Stream Grab archives, and zip files are assumed to be Twitter personal
+//! data exports.
+use crate::import::{Error, Session, Sink};
+use memory_lol_import::{Importer, Registry};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Read, Seek};
+use std::path::Path;
+
+const STDIN_PATH: &str = "-";
+
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const BZIP2_MAGIC: &[u8] = b"BZh";
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const TAR_MAGIC: &[u8] = b"ustar";
+const TAR_MAGIC_OFFSET: usize = 257;
+
+/// The maximum number of bytes read from the first line for format detection.
+const DETECTION_LIMIT_BYTES: u64 = 1024 * 1024;
+
+pub type Input = Box<dyn BufRead>;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Compression {
+    None,
+    Zstd,
+    Gzip,
+    Bzip2,
+    Xz,
+}
+
+impl Compression {
+    pub fn detect(prefix: &[u8]) -> Self {
+        if prefix.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else if prefix.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if prefix.starts_with(BZIP2_MAGIC) {
+            Compression::Bzip2
+        } else if prefix.starts_with(XZ_MAGIC) {
+            Compression::Xz
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// The formats that can be detected or selected by name: the built-in formats and WARC files.
+pub fn registry() -> Registry {
+    let mut registry = Registry::default();
+    registry.register(crate::warc::Warc);
+    registry
+}
+
+#[derive(Clone, Copy)]
+pub enum Format<'a> {
+    /// A format read by a registered importer.
+    Entries(&'a dyn Importer),
+    /// Twitter Stream Grab tar archive.
+    Tar,
+    /// Twitter personal data export.
+    Archive,
+}
+
+impl Format<'_> {
+    pub fn name(&self) -> &str {
+        match self {
+            Format::Entries(importer) => importer.name(),
+            Format::Tar => "tar",
+            Format::Archive => "archive",
+        }
+    }
+}
+
+/// Open a file (or standard input for `-`), decompressing it if necessary.
+pub fn open(path: &str) -> Result<Input, Error> {
+    decompress(open_raw(path)?)
+}
+
+/// Open and decompress a file (or standard input for `-`) and detect its format.
+///
+/// Zip archives are not decompressed, and for them the returned input is the raw content.
+pub fn open_detected<'a>(
+    registry: &'a Registry,
+    path: &str,
+) -> Result<(Option<Format<'a>>, Input), Error> {
+    let mut reader = open_raw(path)?;
+    let prefix = reader.fill_buf()?;
+
+    if prefix.starts_with(ZIP_MAGIC) {
+        return Ok((Some(Format::Archive), Box::new(reader)));
+    }
+
+    let mut reader = decompress(reader)?;
+
+    let mut first_line = vec![];
+    (&mut reader)
+        .take(DETECTION_LIMIT_BYTES)
+        .read_until(b'\n', &mut first_line)?;
+
+    let format = if first_line
+        .get(TAR_MAGIC_OFFSET..)
+        .filter(|header| header.starts_with(TAR_MAGIC))
+        .is_some()
+    {
+        Some(Format::Tar)
+    } else {
+        registry.detect(&first_line).map(Format::Entries)
+    };
+
+    Ok((format, Box::new(Cursor::new(first_line).chain(reader))))
+}
+
+/// Detect the format of a file (or standard input for `-`) and add its observations to the
+/// session, returning the format (or `None` if it was not recognized).
+pub fn load<'a, S: Sink>(
+    session: &mut Session<S>,
+    registry: &'a Registry,
+    path: &str,
+) -> Result<Option<Format<'a>>, Error> {
+    let (format, mut reader) = open_detected(registry, path)?;
+
+    match format {
+        Some(Format::Entries(importer)) => session.load(importer, reader)?,
+        Some(Format::Tar) => {
+            crate::stream_grab::load(session, reader, path)?;
+        }
+        Some(Format::Archive) if path == STDIN_PATH => {
+            let mut contents = vec![];
+            reader.read_to_end(&mut contents)?;
+            crate::archive::load(session, Cursor::new(contents), path)?;
+        }
+        Some(Format::Archive) => {
+            crate::archive::load(session, File::open(path)?, path)?;
+        }
+        None => {}
+    }
+
+    Ok(format)
+}
+
+/// Open a file for random access, reading standard input into memory for `-`.
+pub fn open_seekable(path: &str) -> Result<Box<dyn ReadSeek>, Error> {
+    if path == STDIN_PATH {
+        let mut contents = vec![];
+        std::io::stdin().read_to_end(&mut contents)?;
+
+        Ok(Box::new(Cursor::new(contents)))
+    } else {
+        Ok(Box::new(File::open(path)?))
+    }
+}
+
+/// Hex-encoded SHA-256 hash of a file's contents.
+pub fn hash_file(path: &Path) -> Result<String, Error> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+fn open_raw(path: &str) -> Result<BufReader<Box<dyn Read>>, Error> {
+    let reader: Box<dyn Read> = if path == STDIN_PATH {
+        Box::new(std::io::stdin())
+    } else {
+        Box::new(File::open(path)?)
+    };
+
+    Ok(BufReader::new(reader))
+}
+
+/// Wrap a reader in the decoder for its compression format (if any).
+pub fn decompress<'a, R: BufRead + 'a>(mut reader: R) -> Result<Box<dyn BufRead + 'a>, Error> {
+    Ok(match Compression::detect(reader.fill_buf()?) {
+        Compression::None => Box::new(reader),
+        Compression::Zstd => Box::new(BufReader::new(zstd::stream::read::Decoder::with_buffer(
+            reader,
+        )?)),
+        Compression::Gzip => Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(reader))),
+        Compression::Bzip2 => Box::new(BufReader::new(bzip2::read::MultiBzDecoder::new(reader))),
+        Compression::Xz => Box::new(BufReader::new(xz2::read::XzDecoder::new_multi_decoder(
+            reader,
+        ))),
+    })
+}
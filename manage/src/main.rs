@@ -1,39 +1,83 @@
-use crate::import::{Session, UpdateMode};
-use chrono::{TimeZone, Utc};
-use clap::{ArgAction, Parser};
-use memory_lol::db::{Database, ReadOnly, Table, Writeable};
+use crate::dry_run::DryRun;
+use crate::export::ExportFormat;
+use crate::import::{Session, SessionCounts, Sink, UpdateMode};
+use crate::imports::BatchSink;
+use crate::rejects::{ErrorPolicy, Rejects};
+use chrono::{NaiveDate, Utc};
+use clap::{ArgAction, Args, Parser};
+use memory_lol::db::{
+    accounts::MergePolicy,
+    audit::{AuditCounts, Finding},
+    diff::DiffCounts,
+    format::FORMAT_VERSION,
+    merge::PairFilter,
+    Database, DatabaseOptions, ReadOnly, Table, Writeable,
+};
+use memory_lol_import::{formats, Importer};
 use simplelog::LevelFilter;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Read};
-use zstd::stream::read::Decoder;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::num::NonZeroUsize;
 
+mod archive;
+mod batch;
+mod dry_run;
+mod export;
 mod import;
+mod imports;
+mod input;
+mod rejects;
+mod reshard;
+mod sqlite;
+mod stream_grab;
+mod warc;
 
 fn main() -> Result<(), Error> {
     let opts: Opts = Opts::parse();
     init_logging(opts.verbose)?;
 
+    let db_options = match &opts.db_options {
+        Some(path) => DatabaseOptions::from_toml_file(path)?,
+        None => DatabaseOptions::default(),
+    };
+
     match opts.command {
         Command::LookupId { id } => {
-            let db = Database::<ReadOnly>::open(&opts.db)?;
+            let db = Database::<ReadOnly>::open_with_options(&opts.db, &db_options)?;
             let result = db.lookup_by_user_id(id)?;
             let mut results = result.iter().collect::<Vec<_>>();
             results.sort_by_key(|(screen_name, _)| screen_name.to_string());
 
-            for (screen_name, dates) in results {
-                println!(
-                    "{}: {}",
-                    screen_name,
-                    dates
-                        .iter()
-                        .map(|date| date.to_string())
-                        .collect::<Vec<_>>()
-                        .join(", ")
-                );
+            match memory_lol::snowflake::creation_time(id) {
+                Some(created) if created.estimated => {
+                    println!("Created: {} (estimated)", created.timestamp.to_rfc3339())
+                }
+                Some(created) => println!("Created: {}", created.timestamp.to_rfc3339()),
+                None => {}
+            }
+
+            for (screen_name, observations) in results {
+                let dates = observations
+                    .dates
+                    .iter()
+                    .map(|date| date.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                match observations.timestamps {
+                    Some((first, last)) => println!(
+                        "{}: {} (first seen {}, last seen {})",
+                        screen_name,
+                        dates,
+                        first.to_rfc3339(),
+                        last.to_rfc3339()
+                    ),
+                    None => println!("{}: {}", screen_name, dates),
+                }
             }
         }
         Command::LookupIds => {
-            let db = Database::<ReadOnly>::open(&opts.db)?;
+            let db = Database::<ReadOnly>::open_with_options(&opts.db, &db_options)?;
             for line in std::io::stdin().lines() {
                 let line = line?;
                 let id = line
@@ -44,12 +88,13 @@ fn main() -> Result<(), Error> {
                 let mut results = result.iter().collect::<Vec<_>>();
                 results.sort_by_key(|(screen_name, _)| screen_name.to_string());
 
-                for (screen_name, dates) in results {
+                for (screen_name, observations) in results {
                     println!(
                         "{},{},{}",
                         id,
                         screen_name,
-                        dates
+                        observations
+                            .dates
                             .iter()
                             .map(|date| date.to_string())
                             .collect::<Vec<_>>()
@@ -59,15 +104,16 @@ fn main() -> Result<(), Error> {
             }
         }
         Command::Dump => {
-            let db = Database::<ReadOnly>::open(&opts.db)?;
+            let db = Database::<ReadOnly>::open_with_options(&opts.db, &db_options)?;
             for pair in db.accounts.pairs() {
-                let (id, screen_name, dates) = pair?;
+                let (id, screen_name, observations) = pair?;
 
                 println!(
                     "{},{},{}",
                     id,
                     screen_name,
-                    dates
+                    observations
+                        .dates
                         .iter()
                         .map(|date| date.format("%Y-%m-%d").to_string())
                         .collect::<Vec<_>>()
@@ -76,7 +122,7 @@ fn main() -> Result<(), Error> {
             }
         }
         Command::Stats => {
-            let db = Database::<ReadOnly>::open(&opts.db)?;
+            let db = Database::<ReadOnly>::open_with_options(&opts.db, &db_options)?;
             if let Some(count) = db.accounts.get_estimated_key_count()? {
                 println!("Estimated account keys: {count}");
             }
@@ -84,6 +130,9 @@ fn main() -> Result<(), Error> {
                 println!("Estimated screen name keys: {count}");
             }
 
+            println!("Format version: {}", db.get_format_version()?);
+            println!("Merge policy: {}", db.get_merge_policy()?);
+
             let (account_counts, screen_name_counts) = db.get_counts()?;
             println!("Accounts: {}", account_counts.id_count);
             println!("Pairs: {}", account_counts.pair_count);
@@ -91,7 +140,7 @@ fn main() -> Result<(), Error> {
             println!("Screen name mappings: {}", screen_name_counts.mapping_count);
         }
         Command::DateCounts => {
-            let db = Database::<ReadOnly>::open(&opts.db)?;
+            let db = Database::<ReadOnly>::open_with_options(&opts.db, &db_options)?;
             let date_counts = db.accounts.get_date_counts()?;
 
             for (date, count) in date_counts {
@@ -99,7 +148,7 @@ fn main() -> Result<(), Error> {
             }
         }
         Command::MostScreenNames { count } => {
-            let db = Database::<ReadOnly>::open(&opts.db)?;
+            let db = Database::<ReadOnly>::open_with_options(&opts.db, &db_options)?;
             let most_screen_names = db.accounts.get_most_screen_names(count)?;
 
             for (id, screen_names) in most_screen_names {
@@ -107,7 +156,7 @@ fn main() -> Result<(), Error> {
             }
         }
         Command::MostReused { count } => {
-            let db = Database::<ReadOnly>::open(&opts.db)?;
+            let db = Database::<ReadOnly>::open_with_options(&opts.db, &db_options)?;
             let most_reused = db.screen_names.get_most_reused(count)?;
 
             for (screen_name, ids) in most_reused {
@@ -115,40 +164,244 @@ fn main() -> Result<(), Error> {
                 println!("{},{},{}", screen_name, ids.len(), id_strings.join(";"));
             }
         }
-        Command::ImportMentions { input, zst } => {
-            let db = Database::<Writeable>::open(&opts.db)?;
-            let file = File::open(input)?;
+        Command::CaseRenames => {
+            let db = Database::<ReadOnly>::open_with_options(&opts.db, &db_options)?;
 
-            let source: Box<dyn Read> = if zst {
-                Box::new(Decoder::new(file)?)
-            } else {
-                Box::new(file)
-            };
+            for (id, screen_names) in db.accounts.get_case_only_renames()? {
+                println!("{},{}", id, screen_names.join(";"));
+            }
+        }
+        Command::Import {
+            paths,
+            format,
+            memory_limit,
+            options,
+            errors,
+        } => {
+            let registry = input::registry();
+            let importer = format
+                .map(|name| {
+                    registry
+                        .get(&name)
+                        .ok_or(Error::UnknownFormat(name.clone()))
+                })
+                .transpose()?;
+
+            run_import(
+                &opts.db,
+                &db_options,
+                &options,
+                errors.rejects()?,
+                "import",
+                &paths,
+                |sink, rejects| {
+                    let mut session = Session::new(sink, UpdateMode::Range)
+                        .with_memory_limit(memory_limit)?
+                        .with_rejects(rejects.clone());
 
-            let session = Session::load_mentions(source)?;
-            let count = session.update(&db, UpdateMode::Range)?;
+                    for path in &paths {
+                        session.set_input(path);
 
-            log::info!("Updated {} entries", count);
+                        match importer {
+                            Some(importer) => session.load(importer, input::open(path)?)?,
+                            None => match input::load(&mut session, &registry, path)? {
+                                Some(format) => log::info!("Read {} as {}", path, format.name()),
+                                None => log::warn!("Skipping {} (unrecognized format)", path),
+                            },
+                        }
+                    }
+
+                    Ok(session.finish()?)
+                },
+            )?;
+        }
+        Command::Formats => {
+            for importer in input::registry().iter() {
+                println!("{}: {}", importer.name(), importer.description());
+            }
         }
-        Command::ImportJson { input, zst } => {
-            let db = Database::<Writeable>::open(&opts.db)?;
-            let file = File::open(input)?;
+        Command::ImportMentions {
+            input,
+            zst: _,
+            memory_limit,
+            options,
+            errors,
+        } => {
+            let source = input::open(&input)?;
 
-            let source: Box<dyn Read> = if zst {
-                Box::new(Decoder::new(file)?)
-            } else {
-                Box::new(file)
-            };
+            run_import(
+                &opts.db,
+                &db_options,
+                &options,
+                errors.rejects()?,
+                "import-mentions",
+                std::slice::from_ref(&input),
+                |sink, rejects| {
+                    let mut session = Session::new(sink, UpdateMode::Range)
+                        .with_memory_limit(memory_limit)?
+                        .with_rejects(rejects.for_input(&input));
+                    session.load(&formats::Mentions, source)?;
+                    Ok(session.finish()?)
+                },
+            )?;
+        }
+        Command::ImportJson {
+            input,
+            zst: _,
+            memory_limit,
+            options,
+            errors,
+        } => {
+            let reader = input::open(&input)?;
 
-            let reader = BufReader::new(source);
+            run_import(
+                &opts.db,
+                &db_options,
+                &options,
+                errors.rejects()?,
+                "import-json",
+                std::slice::from_ref(&input),
+                |sink, rejects| {
+                    let mut session = Session::new(sink, UpdateMode::Range)
+                        .with_memory_limit(memory_limit)?
+                        .with_rejects(rejects.for_input(&input));
+                    session.load(&formats::Profiles, reader)?;
+                    Ok(session.finish()?)
+                },
+            )?;
+        }
+        Command::ImportTweets {
+            input,
+            zst: _,
+            memory_limit,
+            options,
+            errors,
+        } => {
+            let reader = input::open(&input)?;
+
+            run_import(
+                &opts.db,
+                &db_options,
+                &options,
+                errors.rejects()?,
+                "import-tweets",
+                std::slice::from_ref(&input),
+                |sink, rejects| {
+                    let mut session = Session::new(sink, UpdateMode::Range)
+                        .with_memory_limit(memory_limit)?
+                        .with_rejects(rejects.for_input(&input));
+                    session.load(&formats::Tweets, reader)?;
+                    Ok(session.finish()?)
+                },
+            )?;
+        }
+        Command::ImportWarc {
+            input,
+            sources,
+            memory_limit,
+            options,
+            errors,
+        } => {
+            run_import(
+                &opts.db,
+                &db_options,
+                &options,
+                errors.rejects()?,
+                "import-warc",
+                &input,
+                |sink, rejects| {
+                    let mut session = Session::new(sink, UpdateMode::Range)
+                        .with_memory_limit(memory_limit)?
+                        .with_rejects(rejects.clone());
+
+                    if let Some(sources) = sources {
+                        session = session
+                            .with_source_log(Box::new(BufWriter::new(File::create(sources)?)));
+                    }
+
+                    for path in &input {
+                        session.set_input(path);
+                        session.load(&warc::Warc, input::open(path)?)?;
+                    }
+
+                    Ok(session.finish()?)
+                },
+            )?;
+        }
+        Command::ImportStreamGrab {
+            input,
+            memory_limit,
+            options,
+            errors,
+        } => {
+            run_import(
+                &opts.db,
+                &db_options,
+                &options,
+                errors.rejects()?,
+                "import-stream-grab",
+                &input,
+                |sink, rejects| {
+                    let mut session = Session::new(sink, UpdateMode::Range)
+                        .with_memory_limit(memory_limit)?
+                        .with_rejects(rejects.clone());
 
-            let session = Session::load_json(reader)?;
-            let count = session.update(&db, UpdateMode::Range)?;
+                    for path in &input {
+                        let stream_grab_counts =
+                            stream_grab::load(&mut session, input::open(path)?, path)?;
+
+                        log::info!(
+                            "Read {} tweet files from {} (skipped {} other files)",
+                            stream_grab_counts.files,
+                            path,
+                            stream_grab_counts.skipped
+                        );
+                    }
+
+                    Ok(session.finish()?)
+                },
+            )?;
+        }
+        Command::ImportArchive {
+            input,
+            memory_limit,
+            options,
+            errors,
+        } => {
+            let reader = input::open_seekable(&input)?;
+
+            run_import(
+                &opts.db,
+                &db_options,
+                &options,
+                errors.rejects()?,
+                "import-archive",
+                std::slice::from_ref(&input),
+                |sink, rejects| {
+                    let mut session = Session::new(sink, UpdateMode::Range)
+                        .with_memory_limit(memory_limit)?
+                        .with_rejects(rejects.clone());
+                    let archive_counts = archive::load(&mut session, reader, &input)?;
+
+                    log::info!(
+                        "Read {} observations from {} data files",
+                        archive_counts.entries,
+                        archive_counts.files
+                    );
 
-            log::info!("Updated {} entries", count);
+                    Ok(session.finish()?)
+                },
+            )?;
         }
-        Command::ImportBatch { input, prefix } => {
-            let db = Database::<Writeable>::open(&opts.db)?;
+        Command::ImportBatch {
+            input,
+            prefix,
+            memory_limit,
+            workers,
+            force,
+            options,
+            errors,
+        } => {
             let prefix = prefix.as_ref();
 
             let mut paths = std::fs::read_dir(&input)?
@@ -177,90 +430,122 @@ fn main() -> Result<(), Error> {
                 .collect::<Result<Vec<_>, std::io::Error>>()?;
             paths.sort();
 
-            for directory in paths {
-                log::info!("Importing directory: {}", directory.to_string_lossy());
-
-                let names_file_zst = directory.join("names.csv.zst");
-                let names_file = directory.join("names.csv");
-
-                let profiles_file_zst = directory.join("profiles.ndjson.zst");
-                let profiles_file = directory.join("profiles.ndjson");
-
-                let names_source: Option<Box<dyn Read>> = if names_file_zst.exists() {
-                    let file = File::open(names_file_zst)?;
-                    Some(Box::new(Decoder::new(file)?))
-                } else if names_file.exists() {
-                    let file = File::open(names_file)?;
-                    Some(Box::new(file))
-                } else {
-                    None
-                };
-
-                let profiles_source: Option<Box<dyn Read>> = if profiles_file_zst.exists() {
-                    let file = File::open(profiles_file_zst)?;
-                    Some(Box::new(Decoder::new(file)?))
-                } else if profiles_file.exists() {
-                    let file = File::open(profiles_file)?;
-                    Some(Box::new(file))
-                } else {
-                    None
-                };
-
-                let mut count = 0;
-
-                if let Some(source) = names_source {
-                    log::info!("Importing mentions");
-                    let session = Session::load_mentions(source)?;
-                    count += session.update(&db, UpdateMode::Range)?;
-                }
+            let batch_options = batch::BatchOptions {
+                workers: workers.unwrap_or_else(|| {
+                    std::thread::available_parallelism()
+                        .map(|workers| workers.get())
+                        .unwrap_or(1)
+                }),
+                memory_limit,
+                force,
+            };
 
-                if let Some(source) = profiles_source {
-                    log::info!("Importing profiles");
-                    let reader = BufReader::new(source);
-                    let session = Session::load_json(reader)?;
-                    count += session.update(&db, UpdateMode::Range)?;
-                }
+            let rejects = errors.rejects()?;
+
+            if options.dry_run {
+                let db = Database::<ReadOnly>::open_with_options(&opts.db, &db_options)?;
+                let mut dry_run = DryRun::new(&db);
+                let counts =
+                    batch::import_batch(&db, &mut dry_run, &paths, &batch_options, &rejects)?;
+
+                counts.rejected.log_summary();
+                dry_run.finish(&counts.rejected).print();
+            } else {
+                let db = Database::<Writeable>::open_with_options(&opts.db, &db_options)?;
+                let inputs = paths
+                    .iter()
+                    .map(|path| path.to_string_lossy().to_string())
+                    .collect::<Vec<_>>();
+                let import_batch = imports::start(
+                    &db,
+                    "import-batch",
+                    &inputs,
+                    options.operator(),
+                    options.record_pairs,
+                )?;
+                let counts = batch::import_batch(
+                    &db,
+                    BatchSink::new(&db, &import_batch),
+                    &paths,
+                    &batch_options,
+                    &rejects,
+                )?;
+                let import_batch = imports::finish(
+                    &db,
+                    import_batch,
+                    counts.entries,
+                    counts.rejected.total() as u64,
+                )?;
 
-                log::info!("Updated {} entries", count);
+                counts.rejected.log_summary();
+
+                log::info!(
+                    "Imported {} directories ({} entries), skipped {} (import batch {})",
+                    counts.imported,
+                    counts.entries,
+                    counts.skipped,
+                    import_batch.id
+                );
+            }
+        }
+        Command::Imports {
+            command: ImportsCommand::List,
+        } => {
+            let db = Database::<ReadOnly>::open_with_options(&opts.db, &db_options)?;
+
+            for batch in db.metadata.get_import_batches()? {
+                imports::print(&batch);
             }
         }
+        Command::Imports {
+            command: ImportsCommand::Rollback { batch },
+        } => {
+            let db = Database::<Writeable>::open_with_options(&opts.db, &db_options)?;
+            let counts = db.rollback_import_batch(batch)?;
+
+            println!("Updated pairs: {}", counts.updated_pairs);
+            println!("Removed pairs: {}", counts.removed_pairs);
+            println!("Removed dates: {}", counts.removed_dates);
+        }
         Command::CompactRanges => {
-            let db = Database::<Writeable>::open(&opts.db)?;
+            let db = Database::<Writeable>::open_with_options(&opts.db, &db_options)?;
             db.accounts.compact_ranges()?;
         }
-        Command::ImportMulti => {
-            let db = Database::<Writeable>::open(&opts.db)?;
-            let stdin = std::io::stdin();
-            for line in stdin.lock().lines() {
-                let line = line?;
-                let parts = line.split(',').collect::<Vec<_>>();
-                let user_id = parts
-                    .first()
-                    .and_then(|value| value.parse::<u64>().ok())
-                    .ok_or_else(|| Error::InvalidImportLine(line.clone()))?;
-                let screen_name = parts
-                    .get(1)
-                    .ok_or_else(|| Error::InvalidImportLine(line.clone()))?;
+        Command::ImportMulti { options, errors } => {
+            run_import(
+                &opts.db,
+                &db_options,
+                &options,
+                errors.rejects()?,
+                "import-multi",
+                &["-".to_string()],
+                |sink, rejects| {
+                    let stdin = std::io::stdin();
+                    let mut rejects = rejects.for_input("stdin");
+                    let mut counts = SessionCounts::default();
 
-                let mut dates = vec![];
+                    for entry in formats::Multi.entries(Box::new(stdin.lock())) {
+                        match entry {
+                            Ok(entry) => {
+                                let observations =
+                                    import::to_observations(&entry.snapshots, UpdateMode::All);
 
-                for part in &parts[2..] {
-                    let timestamp = part
-                        .parse::<i64>()
-                        .ok()
-                        .and_then(|value_timestamp| Utc.timestamp_opt(value_timestamp, 0).single())
-                        .ok_or_else(|| Error::InvalidImportLine(line.clone()))?;
-                    dates.push(timestamp.naive_utc().date());
-                }
+                                sink.write(vec![(entry.id, entry.screen_name, observations)])?;
+                                counts.entries += 1;
+                            }
+                            Err(error) => rejects.handle(error)?,
+                        }
+                    }
 
-                dates.sort();
-                dates.dedup();
+                    rejects.flush()?;
+                    counts.rejected = rejects.counts().clone();
 
-                db.insert(user_id, screen_name, dates)?;
-            }
+                    Ok(counts)
+                },
+            )?;
         }
         Command::Remove => {
-            let db = Database::<Writeable>::open(&opts.db)?;
+            let db = Database::<Writeable>::open_with_options(&opts.db, &db_options)?;
             let stdin = std::io::stdin();
             for line in stdin.lock().lines() {
                 let line = line?;
@@ -277,9 +562,190 @@ fn main() -> Result<(), Error> {
             }
         }
         Command::RebuildIndex => {
-            let mut db = Database::<Writeable>::open(&opts.db)?;
+            let mut db = Database::<Writeable>::open_with_options(&opts.db, &db_options)?;
             db.rebuild_index()?;
         }
+        Command::SetMergePolicy { policy } => {
+            let db = Database::<Writeable>::open_with_options(&opts.db, &db_options)?;
+            db.metadata.put_merge_policy(policy)?;
+        }
+        Command::Migrate => {
+            let migrations = Database::<Writeable>::migrate(&opts.db, &db_options)?;
+
+            if migrations.is_empty() {
+                println!("Database format version is current ({FORMAT_VERSION})");
+            }
+
+            for migration in migrations {
+                println!(
+                    "Migrated from format version {} to {}: {}",
+                    migration.from,
+                    migration.to(),
+                    migration.description
+                );
+            }
+        }
+        Command::Export {
+            output,
+            format,
+            dates,
+            batch_size,
+        } => {
+            let db = Database::<ReadOnly>::open_with_options(&opts.db, &db_options)?;
+            let writer = BufWriter::new(File::create(output)?);
+            let count =
+                export::export(db.accounts.pairs(), writer, format, dates, batch_size.get())?;
+
+            log::info!("Exported {} pairs", count);
+        }
+        Command::ExportSqlite { output, ids } => {
+            let db = Database::<ReadOnly>::open_with_options(&opts.db, &db_options)?;
+            let ids = ids.map(|path| read_ids(&path)).transpose()?;
+            let mut connection = rusqlite::Connection::open(output).map_err(sqlite::Error::from)?;
+            let counts = sqlite::export(&db, &mut connection, ids.as_deref())?;
+
+            log::info!(
+                "Exported {} accounts and {} pairs",
+                counts.account_count,
+                counts.pair_count
+            );
+        }
+        Command::ImportSqlite { input, options } => {
+            let connection = rusqlite::Connection::open_with_flags(
+                &input,
+                rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
+            )
+            .map_err(sqlite::Error::from)?;
+
+            run_import(
+                &opts.db,
+                &db_options,
+                &options,
+                Rejects::default(),
+                "import-sqlite",
+                std::slice::from_ref(&input),
+                |sink, _| {
+                    let counts = sqlite::import(sink, &connection)?;
+
+                    log::info!(
+                        "Imported {} accounts and {} pairs",
+                        counts.account_count,
+                        counts.pair_count
+                    );
+
+                    Ok(SessionCounts {
+                        entries: counts.pair_count,
+                        ..SessionCounts::default()
+                    })
+                },
+            )?;
+        }
+        Command::Diff { old, new, log } => {
+            let old = Database::<ReadOnly>::open_with_options(
+                old.as_deref().unwrap_or(&opts.db),
+                &db_options,
+            )?;
+            let new = Database::<ReadOnly>::open_with_options(new, &db_options)?;
+            let mut log = log
+                .map(|path| File::create(path).map(BufWriter::new))
+                .transpose()?;
+            let mut counts = DiffCounts::default();
+
+            for change in old.diff(&new) {
+                let change = change?;
+                counts.update(&change);
+
+                if let Some(log) = log.as_mut() {
+                    serde_json::to_writer(&mut *log, &change)?;
+                    writeln!(log)?;
+                }
+            }
+
+            if let Some(mut log) = log {
+                log.flush()?;
+            }
+
+            println!("New accounts: {}", counts.new_accounts);
+            println!("Removed accounts: {}", counts.removed_accounts);
+            println!("New pairs: {}", counts.new_pairs);
+            println!("Removed pairs: {}", counts.removed_pairs);
+            println!("Extended ranges: {}", counts.extended_ranges);
+            println!("Changed observations: {}", counts.changed_observations);
+        }
+        Command::Merge {
+            from,
+            min_id,
+            max_id,
+            earliest,
+            latest,
+            batch_size,
+        } => {
+            let source = Database::<ReadOnly>::open_with_options(from, &db_options)?;
+            let db = Database::<Writeable>::open_with_options(&opts.db, &db_options)?;
+            let filter = PairFilter {
+                min_id,
+                max_id,
+                earliest,
+                latest,
+            };
+            let counts = db.merge_from(&source, &filter, batch_size)?;
+
+            log::info!(
+                "Merged {} pairs (skipped {})",
+                counts.merged_pairs,
+                counts.skipped_pairs
+            );
+        }
+        Command::Audit {
+            report,
+            fix,
+            quarantine,
+        } => {
+            if fix {
+                let mut db = Database::<Writeable>::open_with_options(&opts.db, &db_options)?;
+                let quarantine = quarantine
+                    .map(|path| Database::<Writeable>::open_with_options(path, &db_options))
+                    .transpose()?;
+                let mut removed = 0;
+
+                run_audit(&db, report, |finding| {
+                    if db.remove_finding(finding, quarantine.as_ref())? {
+                        removed += 1;
+                    }
+
+                    Ok(())
+                })?;
+
+                if removed > 0 {
+                    log::info!("Removed {} records, rebuilding screen name index", removed);
+                    db.rebuild_index()?;
+                }
+            } else {
+                let db = Database::<ReadOnly>::open_with_options(&opts.db, &db_options)?;
+
+                run_audit(&db, report, |_| Ok(()))?;
+            }
+        }
+        Command::Reshard { from, to } => {
+            let source = reshard::Layout::<ReadOnly>::open(
+                from.as_deref().unwrap_or(&opts.db),
+                &db_options,
+            )?;
+            let target = reshard::Layout::<Writeable>::open(&to, &db_options)?;
+            let count = reshard::reshard(&source, &target)?;
+
+            log::info!("Copied {} pairs", count);
+        }
+        Command::ExportCompact { output } => {
+            let db = Database::<ReadOnly>::open_with_options(&opts.db, &db_options)?;
+            let (pair_count, screen_name_count) = memory_lol::store::compact::export(&db, output)?;
+
+            log::info!(
+                "Exported {} pairs and {} screen names",
+                pair_count,
+                screen_name_count
+            );
+        }
     }
 
     Ok(())
@@ -289,8 +755,12 @@ fn main() -> Result<(), Error> {
 pub enum Error {
     #[error("Application database error")]
     AppDb(#[from] memory_lol::db::Error),
+    #[error("Export error")]
+    Export(#[from] crate::export::Error),
     #[error("Import error")]
     Import(#[from] crate::import::Error),
+    #[error("SQLite error")]
+    Sqlite(#[from] crate::sqlite::Error),
     #[error("I/O error")]
     Io(#[from] std::io::Error),
     #[error("JSON error")]
@@ -301,6 +771,8 @@ pub enum Error {
     InvalidImportLine(String),
     #[error("Invalid user ID")]
     InvalidUserId(String),
+    #[error("Unknown input format")]
+    UnknownFormat(String),
 }
 
 #[derive(Debug, Parser)]
@@ -312,6 +784,9 @@ struct Opts {
     /// Database directory path
     #[clap(long)]
     db: String,
+    /// TOML file containing RocksDB tuning options
+    #[clap(long)]
+    db_options: Option<String>,
     #[clap(subcommand)]
     command: Command,
 }
@@ -341,23 +816,115 @@ enum Command {
         #[clap(long, default_value = "100")]
         count: usize,
     },
+    /// List the accounts whose screen names differ only in case
+    CaseRenames,
+    /// Import files in any supported format, detecting compression and format automatically
+    Import {
+        /// Input file paths (`-` for standard input)
+        #[clap(required = true)]
+        paths: Vec<String>,
+        /// Read every input in this format instead of detecting it (see the formats command)
+        #[clap(long)]
+        format: Option<String>,
+        /// Write aggregated entries whenever they take more than approximately this many MiB
+        #[clap(long, value_parser = parse_memory_limit)]
+        memory_limit: Option<usize>,
+        #[clap(flatten)]
+        options: ImportOptions,
+        #[clap(flatten)]
+        errors: RejectOptions,
+    },
+    /// List the input formats that can be selected with import --format
+    Formats,
     /// Import a CSV file containing mentions
     ImportMentions {
-        /// NDJSON file path
+        /// CSV file path (`-` for standard input)
         #[clap(long)]
         input: String,
-        /// Use ZSTD compression
-        #[clap(long)]
+        /// Ignored (compression is detected automatically)
+        #[clap(long, hide = true)]
         zst: bool,
+        /// Write aggregated entries whenever they take more than approximately this many MiB
+        #[clap(long, value_parser = parse_memory_limit)]
+        memory_limit: Option<usize>,
+        #[clap(flatten)]
+        options: ImportOptions,
+        #[clap(flatten)]
+        errors: RejectOptions,
     },
     /// Import an NDJSON file
     ImportJson {
-        /// NDJSON file path
+        /// NDJSON file path (`-` for standard input)
         #[clap(long)]
         input: String,
-        /// Use ZSTD compression
+        /// Ignored (compression is detected automatically)
+        #[clap(long, hide = true)]
+        zst: bool,
+        /// Write aggregated entries whenever they take more than approximately this many MiB
+        #[clap(long, value_parser = parse_memory_limit)]
+        memory_limit: Option<usize>,
+        #[clap(flatten)]
+        options: ImportOptions,
+        #[clap(flatten)]
+        errors: RejectOptions,
+    },
+    /// Import an NDJSON file of full tweet objects (API v1.1 tweets or v2 responses)
+    ImportTweets {
+        /// NDJSON file path (`-` for standard input)
         #[clap(long)]
+        input: String,
+        /// Ignored (compression is detected automatically)
+        #[clap(long, hide = true)]
         zst: bool,
+        /// Write aggregated entries whenever they take more than approximately this many MiB
+        #[clap(long, value_parser = parse_memory_limit)]
+        memory_limit: Option<usize>,
+        #[clap(flatten)]
+        options: ImportOptions,
+        #[clap(flatten)]
+        errors: RejectOptions,
+    },
+    /// Import archived Twitter pages from WARC files
+    ImportWarc {
+        /// WARC file path (may be repeated, `-` for standard input)
+        #[clap(long, required = true)]
+        input: Vec<String>,
+        /// Write a CSV file with the capture URL for each observation
+        #[clap(long)]
+        sources: Option<String>,
+        /// Write aggregated entries whenever they take more than approximately this many MiB
+        #[clap(long, value_parser = parse_memory_limit)]
+        memory_limit: Option<usize>,
+        #[clap(flatten)]
+        options: ImportOptions,
+        #[clap(flatten)]
+        errors: RejectOptions,
+    },
+    /// Import Twitter Stream Grab tar archives without extracting them
+    ImportStreamGrab {
+        /// Tar file path (may be repeated and compressed, `-` for standard input)
+        #[clap(long, required = true)]
+        input: Vec<String>,
+        /// Write aggregated entries whenever they take more than approximately this many MiB
+        #[clap(long, value_parser = parse_memory_limit)]
+        memory_limit: Option<usize>,
+        #[clap(flatten)]
+        options: ImportOptions,
+        #[clap(flatten)]
+        errors: RejectOptions,
+    },
+    /// Import a Twitter personal data export (zip file)
+    ImportArchive {
+        /// Zip file path (`-` for standard input)
+        #[clap(long)]
+        input: String,
+        /// Write aggregated entries whenever they take more than approximately this many MiB
+        #[clap(long, value_parser = parse_memory_limit)]
+        memory_limit: Option<usize>,
+        #[clap(flatten)]
+        options: ImportOptions,
+        #[clap(flatten)]
+        errors: RejectOptions,
     },
     /// Import a batch of Twitter Stream Grab output directories
     ImportBatch {
@@ -367,15 +934,309 @@ enum Command {
         /// Directory prefix
         #[clap(long)]
         prefix: Option<String>,
+        /// Write aggregated entries whenever they take more than approximately this many MiB
+        /// (per worker)
+        #[clap(long, value_parser = parse_memory_limit)]
+        memory_limit: Option<usize>,
+        /// Number of directories to parse in parallel (defaults to the number of CPUs)
+        #[clap(long)]
+        workers: Option<usize>,
+        /// Import directories that are already recorded in the import ledger
+        #[clap(long)]
+        force: bool,
+        #[clap(flatten)]
+        options: ImportOptions,
+        #[clap(flatten)]
+        errors: RejectOptions,
+    },
+    /// Show or roll back import batches (the audit log of import runs)
+    Imports {
+        #[clap(subcommand)]
+        command: ImportsCommand,
     },
-    /// Compact ranges in database
+    /// Compact ranges in database (keeping only the first and last observation date)
     CompactRanges,
     /// Import a CSV from stdin with multiple timestamps per row
-    ImportMulti,
+    ImportMulti {
+        #[clap(flatten)]
+        options: ImportOptions,
+        #[clap(flatten)]
+        errors: RejectOptions,
+    },
     /// Remove comma-separated ID-screen name pairs provided from stdin
     Remove,
     /// Rebuild screen name index
     RebuildIndex,
+    /// Set which observation dates are kept for each pair (all or range)
+    SetMergePolicy {
+        /// Merge policy
+        policy: MergePolicy,
+    },
+    /// Upgrade the database to the current format version
+    Migrate,
+    /// Export all pairs in a columnar format
+    Export {
+        /// Output file path
+        #[clap(long)]
+        output: String,
+        /// Output format
+        #[clap(long, value_enum, default_value = "parquet")]
+        format: ExportFormat,
+        /// Include a list column containing all observation dates
+        #[clap(long)]
+        dates: bool,
+        /// Number of rows per record batch (and Parquet row group)
+        #[clap(long, default_value = "65536")]
+        batch_size: NonZeroUsize,
+    },
+    /// Export accounts and pairs to a SQLite database
+    ExportSqlite {
+        /// SQLite database path
+        #[clap(long)]
+        output: String,
+        /// File containing user IDs to export (one per line)
+        #[clap(long)]
+        ids: Option<String>,
+    },
+    /// Import accounts and pairs from a SQLite database written by export-sqlite
+    ImportSqlite {
+        /// SQLite database path
+        #[clap(long)]
+        input: String,
+        #[clap(flatten)]
+        options: ImportOptions,
+    },
+    /// Compare two databases, printing counts for each kind of change
+    Diff {
+        /// Old database directory path (defaults to --db)
+        #[clap(long)]
+        old: Option<String>,
+        /// New database directory path
+        #[clap(long)]
+        new: String,
+        /// Write every change to this file as NDJSON
+        #[clap(long)]
+        log: Option<String>,
+    },
+    /// Merge pairs from another database into this one
+    Merge {
+        /// Source database directory path
+        #[clap(long)]
+        from: String,
+        /// Minimum user ID to merge
+        #[clap(long)]
+        min_id: Option<u64>,
+        /// Maximum user ID to merge
+        #[clap(long)]
+        max_id: Option<u64>,
+        /// Only merge pairs observed on or after this date (YYYY-MM-DD)
+        #[clap(long)]
+        earliest: Option<NaiveDate>,
+        /// Only merge pairs observed on or before this date (YYYY-MM-DD)
+        #[clap(long)]
+        latest: Option<NaiveDate>,
+        /// Number of pairs per write batch
+        #[clap(long, default_value = "10000")]
+        batch_size: usize,
+    },
+    /// Copy all pairs between database layouts (database directories or shard manifest files)
+    Reshard {
+        /// Source database directory or shard manifest path (defaults to --db)
+        #[clap(long)]
+        from: Option<String>,
+        /// Target database directory or shard manifest path
+        #[clap(long)]
+        to: String,
+    },
+    /// Export the database to an immutable compact lookup file
+    ExportCompact {
+        /// Output file path
+        #[clap(long)]
+        output: String,
+    },
+    /// Scan the database for impossible observations and invalid records
+    Audit {
+        /// Write every anomaly to this file as NDJSON
+        #[clap(long)]
+        report: Option<String>,
+        /// Remove offending records (empty date lists and case duplicates are only reported)
+        #[clap(long)]
+        fix: bool,
+        /// Copy removed records to this database directory
+        #[clap(long, requires = "fix")]
+        quarantine: Option<String>,
+    },
+}
+
+#[derive(Debug, Parser)]
+enum ImportsCommand {
+    /// List import batches
+    List,
+    /// Remove the observation dates contributed only by an import batch (which must have been
+    /// imported with --record-pairs)
+    Rollback {
+        /// Import batch ID
+        batch: u64,
+    },
+}
+
+/// Options shared by the import commands.
+#[derive(Args, Debug)]
+struct ImportOptions {
+    /// Report what the import would change without writing to the database
+    #[clap(long)]
+    dry_run: bool,
+    /// Record the pairs written by this import, so that it can be rolled back
+    #[clap(long)]
+    record_pairs: bool,
+    /// Operator name for the import audit log (defaults to the USER environment variable)
+    #[clap(long)]
+    operator: Option<String>,
+}
+
+impl ImportOptions {
+    fn operator(&self) -> Option<String> {
+        self.operator.clone().or_else(|| std::env::var("USER").ok())
+    }
+}
+
+/// Error handling options for the import commands that read line- or record-based inputs.
+#[derive(Args, Debug)]
+struct RejectOptions {
+    /// How to handle input lines (or records) that cannot be parsed
+    #[clap(long, value_enum, default_value_t = ErrorPolicy::Skip)]
+    on_error: ErrorPolicy,
+    /// CSV file for lines rejected in quarantine mode (input, line number, kind, detail, line)
+    #[clap(long, required_if_eq("on_error", "quarantine"))]
+    rejects: Option<String>,
+}
+
+impl RejectOptions {
+    fn rejects(&self) -> Result<Rejects, Error> {
+        let writer = match (&self.rejects, self.on_error) {
+            (Some(path), ErrorPolicy::Quarantine) => {
+                Some(Box::new(BufWriter::new(File::create(path)?)) as Box<dyn Write + Send>)
+            }
+            _ => None,
+        };
+
+        Ok(Rejects::new(self.on_error, writer))
+    }
+}
+
+/// Read user IDs from a file (one per line, ignoring blank lines).
+fn read_ids(path: &str) -> Result<Vec<u64>, Error> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut ids = vec![];
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+
+        if !line.is_empty() {
+            ids.push(
+                line.parse::<u64>()
+                    .map_err(|_| Error::InvalidUserId(line.to_string()))?,
+            );
+        }
+    }
+
+    Ok(ids)
+}
+
+/// Scan the database for anomalies, printing counts and optionally writing a report, and passing
+/// each finding to `handle`.
+fn run_audit<M, F>(db: &Database<M>, report: Option<String>, mut handle: F) -> Result<(), Error>
+where
+    F: FnMut(&Finding) -> Result<(), Error>,
+{
+    let mut report = report
+        .map(|path| File::create(path).map(BufWriter::new))
+        .transpose()?;
+    let mut counts = AuditCounts::default();
+
+    for finding in db.audit(Utc::now().date_naive()) {
+        let finding = finding?;
+        counts.update(&finding.anomaly);
+
+        if let Some(report) = report.as_mut() {
+            serde_json::to_writer(&mut *report, &finding.anomaly)?;
+            writeln!(report)?;
+        }
+
+        handle(&finding)?;
+    }
+
+    if let Some(mut report) = report {
+        report.flush()?;
+    }
+
+    println!("Invalid keys: {}", counts.invalid_keys);
+    println!("Invalid values: {}", counts.invalid_values);
+    println!("Invalid screen names: {}", counts.invalid_screen_names);
+    println!("Observed before creation: {}", counts.before_creation);
+    println!("Future dates: {}", counts.future_dates);
+    println!("Empty date lists: {}", counts.empty_dates);
+    println!("Case duplicates: {}", counts.case_duplicates);
+
+    Ok(())
+}
+
+/// Run an import against the database, or (with `--dry-run`) report what it would change.
+fn run_import<F>(
+    db_path: &str,
+    db_options: &DatabaseOptions,
+    options: &ImportOptions,
+    rejects: Rejects,
+    command: &str,
+    inputs: &[String],
+    load: F,
+) -> Result<(), Error>
+where
+    F: FnOnce(&mut dyn Sink, &Rejects) -> Result<SessionCounts, Error>,
+{
+    if options.dry_run {
+        let db = Database::<ReadOnly>::open_with_options(db_path, db_options)?;
+        let mut dry_run = DryRun::new(&db);
+        let counts = load(&mut dry_run, &rejects)?;
+
+        counts.rejected.log_summary();
+        dry_run.finish(&counts.rejected).print();
+    } else {
+        let db = Database::<Writeable>::open_with_options(db_path, db_options)?;
+        let batch = imports::start(
+            &db,
+            command,
+            inputs,
+            options.operator(),
+            options.record_pairs,
+        )?;
+        let counts = load(&mut BatchSink::new(&db, &batch), &rejects)?;
+        let batch = imports::finish(
+            &db,
+            batch,
+            counts.entries as u64,
+            counts.rejected.total() as u64,
+        )?;
+
+        counts.rejected.log_summary();
+        log::info!(
+            "Updated {} entries (import batch {})",
+            counts.entries,
+            batch.id
+        );
+    }
+
+    Ok(())
+}
+
+/// Parse a memory limit in MiB as a number of bytes.
+fn parse_memory_limit(value: &str) -> Result<usize, String> {
+    value
+        .parse::<usize>()
+        .map_err(|error| error.to_string())?
+        .checked_mul(1024 * 1024)
+        .ok_or_else(|| "memory limit is too large".to_string())
 }
 
 fn select_log_level_filter(verbosity: u8) -> LevelFilter {
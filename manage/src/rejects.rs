@@ -0,0 +1,167 @@
+//! Handling of input lines that cannot be imported.
+use crate::import::Error;
+use memory_lol_import::RejectKind;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum ErrorPolicy {
+    /// Stop the import at the first rejected line
+    Abort,
+    /// Skip rejected lines
+    #[default]
+    Skip,
+    /// Skip rejected lines and write them to the rejects file
+    Quarantine,
+}
+
+/// Counts of rejected lines by kind.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RejectCounts(BTreeMap<RejectKind, usize>);
+
+impl RejectCounts {
+    pub fn total(&self) -> usize {
+        self.0.values().sum()
+    }
+
+    pub fn get(&self, kind: RejectKind) -> usize {
+        self.0.get(&kind).copied().unwrap_or_default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (RejectKind, usize)> + '_ {
+        self.0.iter().map(|(kind, count)| (*kind, *count))
+    }
+
+    pub fn update(&mut self, kind: RejectKind) {
+        *self.0.entry(kind).or_default() += 1;
+    }
+
+    pub fn merge(&mut self, other: &RejectCounts) {
+        for (kind, count) in other.iter() {
+            *self.0.entry(kind).or_default() += count;
+        }
+    }
+
+    pub fn log_summary(&self) {
+        for (kind, count) in self.iter() {
+            log::warn!("Rejected {} lines ({})", count, kind);
+        }
+    }
+}
+
+type RejectWriter = Arc<Mutex<csv::Writer<Box<dyn Write + Send>>>>;
+
+/// Applies an error policy to rejected lines from one input, counting them and (in quarantine
+/// mode) writing CSV rows with the input name, line number, kind, error detail, and line contents.
+///
+/// Clones share the rejects file, so a single file can collect the rejected lines of several
+/// inputs (including inputs read on different threads).
+#[derive(Clone, Default)]
+pub struct Rejects {
+    policy: ErrorPolicy,
+    writer: Option<RejectWriter>,
+    input: String,
+    counts: RejectCounts,
+}
+
+impl Rejects {
+    pub fn new(policy: ErrorPolicy, writer: Option<Box<dyn Write + Send>>) -> Self {
+        Self {
+            policy,
+            writer: writer.map(|writer| {
+                Arc::new(Mutex::new(
+                    csv::WriterBuilder::new()
+                        .has_headers(false)
+                        .from_writer(writer),
+                ))
+            }),
+            input: String::new(),
+            counts: RejectCounts::default(),
+        }
+    }
+
+    /// A handle with the same policy and rejects file for the given input, with empty counts.
+    pub fn for_input(&self, input: &str) -> Self {
+        Self {
+            policy: self.policy,
+            writer: self.writer.clone(),
+            input: input.to_string(),
+            counts: RejectCounts::default(),
+        }
+    }
+
+    pub fn counts(&self) -> &RejectCounts {
+        &self.counts
+    }
+
+    pub fn reject(
+        &mut self,
+        kind: RejectKind,
+        line: u64,
+        detail: &str,
+        contents: &str,
+    ) -> Result<(), Error> {
+        log::warn!(
+            "Rejected line {} of {} ({}): {}",
+            line,
+            self.input,
+            kind,
+            detail
+        );
+
+        match self.policy {
+            ErrorPolicy::Abort => Err(Error::Rejected {
+                input: self.input.clone(),
+                line,
+                kind,
+                detail: detail.to_string(),
+            }),
+            ErrorPolicy::Skip => {
+                self.counts.update(kind);
+                Ok(())
+            }
+            ErrorPolicy::Quarantine => {
+                self.counts.update(kind);
+
+                if let Some(writer) = &self.writer {
+                    let mut writer = writer.lock().map_err(|_| Error::RejectsUnavailable)?;
+                    writer.write_record([
+                        self.input.as_str(),
+                        line.to_string().as_str(),
+                        kind.to_string().as_str(),
+                        detail,
+                        contents,
+                    ])?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Reject the line if the error is an invalid input line, and return any other error.
+    pub fn handle(&mut self, error: memory_lol_import::Error) -> Result<(), Error> {
+        match error {
+            memory_lol_import::Error::InvalidLine {
+                line,
+                kind,
+                detail,
+                contents,
+            } => self.reject(kind, line, &detail, &contents),
+            error => Err(error.into()),
+        }
+    }
+
+    /// Flush the rejects file.
+    pub fn flush(&self) -> Result<(), Error> {
+        if let Some(writer) = &self.writer {
+            writer
+                .lock()
+                .map_err(|_| Error::RejectsUnavailable)?
+                .flush()?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use memory_lol::db::{
+    accounts::Observations, sharded::ShardedDatabase, Database, DatabaseOptions, Error, Mode,
+    Writeable,
+};
+use std::path::Path;
+
+type Pair = (u64, String, Observations);
+
+/// Either a single database directory or a sharded database manifest file.
+pub enum Layout<M> {
+    Single(Box<Database<M>>),
+    Sharded(ShardedDatabase<M>),
+}
+
+impl<M: Mode + Sync + Send + 'static> Layout<M> {
+    /// Paths to files are treated as manifests, and any other path as a database directory.
+    pub fn open(path: &str, options: &DatabaseOptions) -> Result<Self, Error> {
+        if Path::new(path).is_file() {
+            Ok(Self::Sharded(ShardedDatabase::open_with_options(
+                path, options,
+            )?))
+        } else {
+            Ok(Self::Single(Box::new(Database::open_with_options(
+                path, options,
+            )?)))
+        }
+    }
+
+    pub fn pairs(&self) -> Box<dyn Iterator<Item = Result<Pair, Error>> + '_> {
+        match self {
+            Self::Single(db) => Box::new(db.accounts.pairs()),
+            Self::Sharded(db) => Box::new(db.pairs()),
+        }
+    }
+}
+
+impl Layout<Writeable> {
+    pub fn insert_observations(
+        &self,
+        id: u64,
+        screen_name: &str,
+        observations: &Observations,
+    ) -> Result<(), Error> {
+        match self {
+            Self::Single(db) => db.insert_observations(id, screen_name, observations),
+            Self::Sharded(db) => db.insert_observations(id, screen_name, observations),
+        }
+    }
+}
+
+/// Copy every pair from the source to the target, returning the number of pairs copied.
+pub fn reshard<M>(source: &Layout<M>, target: &Layout<Writeable>) -> Result<u64, Error>
+where
+    M: Mode + Sync + Send + 'static,
+{
+    let mut count = 0;
+
+    for pair in source.pairs() {
+        let (id, screen_name, observations) = pair?;
+        target.insert_observations(id, &screen_name, &observations)?;
+        count += 1;
+
+        if count % 1_000_000 == 0 {
+            log::info!("Copied {} pairs", count);
+        }
+    }
+
+    Ok(count)
+}
@@ -0,0 +1,290 @@
+use crate::import::Sink;
+use chrono::{DateTime, NaiveDate, Utc};
+use memory_lol::db::{accounts::Observations, Database};
+use rusqlite::{params, Connection};
+use std::collections::BTreeSet;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS account (
+        id INTEGER PRIMARY KEY
+    );
+    CREATE TABLE IF NOT EXISTS screen_name (
+        id INTEGER PRIMARY KEY,
+        screen_name TEXT NOT NULL UNIQUE,
+        screen_name_lower TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS screen_name_lower_index ON screen_name (screen_name_lower);
+    CREATE TABLE IF NOT EXISTS pair (
+        id INTEGER PRIMARY KEY,
+        account_id INTEGER NOT NULL REFERENCES account (id),
+        screen_name_id INTEGER NOT NULL REFERENCES screen_name (id),
+        first_seen INTEGER,
+        last_seen INTEGER,
+        UNIQUE (account_id, screen_name_id)
+    );
+    CREATE INDEX IF NOT EXISTS pair_screen_name_id_index ON pair (screen_name_id);
+    CREATE TABLE IF NOT EXISTS observation (
+        pair_id INTEGER NOT NULL REFERENCES pair (id),
+        date TEXT NOT NULL,
+        PRIMARY KEY (pair_id, date)
+    ) WITHOUT ROWID;
+";
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("SQLite error")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("Database error")]
+    Db(#[from] memory_lol::db::Error),
+    #[error("Import error")]
+    Import(#[from] crate::import::Error),
+    #[error("User ID out of range for SQLite")]
+    InvalidId(u64),
+    #[error("Invalid user ID in SQLite database")]
+    InvalidSqliteId(i64),
+    #[error("Invalid observation timestamp")]
+    InvalidTimestamp(i64),
+    #[error("Invalid observation date")]
+    InvalidDate(#[from] chrono::ParseError),
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Counts {
+    pub account_count: usize,
+    pub pair_count: usize,
+}
+
+/// Write pairs to a normalized SQLite schema (creating it if necessary).
+///
+/// Dates are stored as `YYYY-MM-DD` strings, and the exact first and last observation times (if
+/// known) as epoch seconds. If a list of user IDs is provided, only those accounts are exported
+/// (each once, in ID order). Pairs that are already in the SQLite database keep their existing
+/// dates, and their observation range is extended.
+pub fn export<M: Sync + Send + 'static>(
+    db: &Database<M>,
+    connection: &mut Connection,
+    ids: Option<&[u64]>,
+) -> Result<Counts, Error> {
+    connection.execute_batch(SCHEMA)?;
+
+    let transaction = connection.transaction()?;
+    let mut writer = Writer::new(&transaction)?;
+
+    match ids {
+        Some(ids) => {
+            for id in ids.iter().copied().collect::<BTreeSet<_>>() {
+                let result = db.lookup_by_user_id(id)?;
+
+                if !result.is_empty() {
+                    writer.insert_account(id)?;
+
+                    for (screen_name, observations) in result {
+                        writer.insert_pair(id, &screen_name, &observations)?;
+                    }
+                }
+            }
+        }
+        None => {
+            let mut last_id = None;
+
+            for pair in db.accounts.pairs() {
+                let (id, screen_name, observations) = pair?;
+
+                if last_id != Some(id) {
+                    writer.insert_account(id)?;
+                    last_id = Some(id);
+                }
+
+                writer.insert_pair(id, &screen_name, &observations)?;
+            }
+        }
+    }
+
+    let counts = writer.counts;
+    drop(writer);
+    transaction.commit()?;
+
+    Ok(counts)
+}
+
+/// Read pairs from a SQLite database with the schema written by [`export`].
+pub fn import<S: Sink>(mut sink: S, connection: &Connection) -> Result<Counts, Error> {
+    let mut statement = connection.prepare(
+        "SELECT pair.id, pair.account_id, screen_name.screen_name, pair.first_seen, pair.last_seen,
+            observation.date
+            FROM pair
+            JOIN screen_name ON screen_name.id = pair.screen_name_id
+            LEFT JOIN observation ON observation.pair_id = pair.id
+            ORDER BY pair.account_id, pair.id, observation.date",
+    )?;
+    let mut rows = statement.query([])?;
+    let mut counts = Counts::default();
+    let mut last_id = None;
+    let mut current: Option<(i64, u64, String, Observations)> = None;
+
+    while let Some(row) = rows.next()? {
+        let pair_id: i64 = row.get(0)?;
+
+        if current.as_ref().map(|(id, _, _, _)| *id) != Some(pair_id) {
+            if let Some((_, id, screen_name, observations)) = current.take() {
+                insert(
+                    &mut sink,
+                    &mut counts,
+                    &mut last_id,
+                    id,
+                    screen_name,
+                    observations,
+                )?;
+            }
+
+            let account_id: i64 = row.get(1)?;
+            let id = u64::try_from(account_id).map_err(|_| Error::InvalidSqliteId(account_id))?;
+            let first_seen: Option<i64> = row.get(3)?;
+            let last_seen: Option<i64> = row.get(4)?;
+
+            let timestamps = match (first_seen, last_seen) {
+                (Some(first_seen), Some(last_seen)) => Some((
+                    epoch_second_to_timestamp(first_seen)?,
+                    epoch_second_to_timestamp(last_seen)?,
+                )),
+                _ => None,
+            };
+
+            current = Some((
+                pair_id,
+                id,
+                row.get(2)?,
+                Observations::new(vec![], timestamps),
+            ));
+        }
+
+        let date: Option<String> = row.get(5)?;
+
+        if let (Some(date), Some((_, _, _, observations))) = (date, current.as_mut()) {
+            observations
+                .dates
+                .push(NaiveDate::parse_from_str(&date, DATE_FORMAT)?);
+        }
+    }
+
+    if let Some((_, id, screen_name, observations)) = current {
+        insert(
+            &mut sink,
+            &mut counts,
+            &mut last_id,
+            id,
+            screen_name,
+            observations,
+        )?;
+    }
+
+    Ok(counts)
+}
+
+/// Pairs are read in account order, so a new account starts whenever the user ID changes.
+fn insert<S: Sink>(
+    sink: &mut S,
+    counts: &mut Counts,
+    last_id: &mut Option<u64>,
+    id: u64,
+    screen_name: String,
+    observations: Observations,
+) -> Result<(), Error> {
+    if *last_id != Some(id) {
+        counts.account_count += 1;
+        *last_id = Some(id);
+    }
+
+    sink.write(vec![(id, screen_name, observations)])?;
+    counts.pair_count += 1;
+
+    Ok(())
+}
+
+struct Writer<'a> {
+    insert_account: rusqlite::Statement<'a>,
+    insert_screen_name: rusqlite::Statement<'a>,
+    select_screen_name: rusqlite::Statement<'a>,
+    insert_pair: rusqlite::Statement<'a>,
+    insert_observation: rusqlite::Statement<'a>,
+    counts: Counts,
+}
+
+impl<'a> Writer<'a> {
+    fn new(connection: &'a Connection) -> Result<Self, Error> {
+        Ok(Self {
+            insert_account: connection.prepare("INSERT OR IGNORE INTO account (id) VALUES (?)")?,
+            insert_screen_name: connection.prepare(
+                "INSERT OR IGNORE INTO screen_name (screen_name, screen_name_lower) VALUES (?, ?)",
+            )?,
+            select_screen_name: connection
+                .prepare("SELECT id FROM screen_name WHERE screen_name = ?")?,
+            insert_pair: connection.prepare(
+                "INSERT INTO pair (account_id, screen_name_id, first_seen, last_seen)
+                    VALUES (?, ?, ?, ?)
+                    ON CONFLICT (account_id, screen_name_id)
+                    DO UPDATE SET
+                        first_seen = MIN(
+                            COALESCE(first_seen, excluded.first_seen),
+                            COALESCE(excluded.first_seen, first_seen)
+                        ),
+                        last_seen = MAX(
+                            COALESCE(last_seen, excluded.last_seen),
+                            COALESCE(excluded.last_seen, last_seen)
+                        )
+                    RETURNING id",
+            )?,
+            insert_observation: connection
+                .prepare("INSERT OR IGNORE INTO observation (pair_id, date) VALUES (?, ?)")?,
+            counts: Counts::default(),
+        })
+    }
+
+    fn insert_account(&mut self, id: u64) -> Result<(), Error> {
+        self.insert_account.execute([to_sqlite_id(id)?])?;
+        self.counts.account_count += 1;
+        Ok(())
+    }
+
+    fn insert_pair(
+        &mut self,
+        id: u64,
+        screen_name: &str,
+        observations: &Observations,
+    ) -> Result<(), Error> {
+        self.insert_screen_name
+            .execute(params![screen_name, screen_name.to_lowercase()])?;
+
+        let screen_name_id: i64 = self
+            .select_screen_name
+            .query_row([screen_name], |row| row.get(0))?;
+
+        let pair_id: i64 = self.insert_pair.query_row(
+            params![
+                to_sqlite_id(id)?,
+                screen_name_id,
+                observations.first_seen().map(|value| value.timestamp()),
+                observations.last_seen().map(|value| value.timestamp()),
+            ],
+            |row| row.get(0),
+        )?;
+
+        for date in &observations.dates {
+            self.insert_observation
+                .execute(params![pair_id, date.format(DATE_FORMAT).to_string()])?;
+        }
+
+        self.counts.pair_count += 1;
+        Ok(())
+    }
+}
+
+fn to_sqlite_id(id: u64) -> Result<i64, Error> {
+    i64::try_from(id).map_err(|_| Error::InvalidId(id))
+}
+
+fn epoch_second_to_timestamp(value: i64) -> Result<DateTime<Utc>, Error> {
+    DateTime::from_timestamp(value, 0).ok_or(Error::InvalidTimestamp(value))
+}
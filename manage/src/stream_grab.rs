@@ -0,0 +1,63 @@
+//! Streaming import of Twitter Stream Grab tar archives.
+//!
+//! The Internet Archive distributes the Stream Grab as tar files containing one file of raw tweets
+//! (one JSON object per line, including deletion notices) per minute, usually compressed with
+//! bzip2 or gzip. Members are read and decompressed in sequence without extracting anything to
+//! disk, so the archive itself may also be compressed or read from standard input.
+use crate::import::{Error, Session, Sink};
+use memory_lol_import::formats::Tweets;
+use std::io::{BufReader, Read};
+use tar::Archive;
+
+const TWEET_FILE_EXTENSION: &str = ".json";
+const COMPRESSION_EXTENSIONS: [&str; 4] = [".bz2", ".gz", ".xz", ".zst"];
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct StreamGrabCounts {
+    pub files: usize,
+    pub skipped: usize,
+}
+
+/// Read the tweet files in a tar archive and add their observations to the session.
+///
+/// Rejected lines are reported against `<name>:<member path>`.
+pub fn load<R: Read, S: Sink>(
+    session: &mut Session<S>,
+    reader: R,
+    name: &str,
+) -> Result<StreamGrabCounts, Error> {
+    let mut archive = Archive::new(reader);
+    let mut counts = StreamGrabCounts::default();
+
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let path = entry.path()?.to_string_lossy().to_string();
+
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        if !is_tweet_file(&path) {
+            log::debug!("Skipping {} in {}", path, name);
+            counts.skipped += 1;
+            continue;
+        }
+
+        session.set_input(&format!("{}:{}", name, path));
+        session.load(&Tweets, crate::input::decompress(BufReader::new(entry))?)?;
+        counts.files += 1;
+
+        log::debug!("Read {} from {}", path, name);
+    }
+
+    Ok(counts)
+}
+
+fn is_tweet_file(path: &str) -> bool {
+    let path = COMPRESSION_EXTENSIONS
+        .iter()
+        .find_map(|extension| path.strip_suffix(extension))
+        .unwrap_or(path);
+
+    path.ends_with(TWEET_FILE_EXTENSION)
+}
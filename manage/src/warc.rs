@@ -0,0 +1,557 @@
+//! Extraction of observations from WARC files containing archived Twitter pages.
+//!
+//! Only successful `response` records for Twitter URLs are considered. User IDs and screen names
+//! are taken from the `data-user-id` and `data-screen-name` attributes used by the legacy web
+//! client, from user objects in JSON embedded in the page (in `json-data` inputs or the
+//! `window.__INITIAL_STATE__` script), and from user objects in JSON responses. Everything found in
+//! a capture is observed at the capture time (the `WARC-Date` header).
+use crate::import::Error;
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use memory_lol_import::{Entries, Importer, RejectKind, ScreenNameEntry};
+use regex::Regex;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::io::{BufRead, Read};
+use std::sync::LazyLock;
+
+const TWITTER_HOSTS: [&str; 4] = [
+    "twitter.com",
+    "www.twitter.com",
+    "mobile.twitter.com",
+    "api.twitter.com",
+];
+const INITIAL_STATE_PREFIX: &str = "window.__INITIAL_STATE__";
+
+static TAG_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^<>]+>").unwrap());
+static USER_ID_ATTRIBUTE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"\bdata-user-id="(\d+)""#).unwrap());
+static SCREEN_NAME_ATTRIBUTE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"\bdata-screen-name="(\w+)""#).unwrap());
+static VALUE_ATTRIBUTE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"\bvalue="([^"]*)""#).unwrap());
+
+/// A successful HTTP response for a Twitter URL.
+#[derive(Clone, Debug)]
+pub struct Capture {
+    pub url: String,
+    pub timestamp: DateTime<Utc>,
+    /// The HTTP response (status line, headers, and body) as archived.
+    pub response: Vec<u8>,
+}
+
+impl Capture {
+    /// All ID-screen name pairs found in the capture, tagged with the capture URL.
+    pub fn extract_entries(&self) -> Vec<ScreenNameEntry> {
+        let mut pairs = BTreeSet::new();
+
+        if let Some((content_type, body)) = parse_response(&self.response) {
+            let body = String::from_utf8_lossy(&body);
+
+            if content_type.contains("json") {
+                if let Ok(value) = serde_json::from_str(&body) {
+                    extract_json_users(&value, &mut pairs);
+                }
+            } else {
+                extract_html_users(&body, &mut pairs);
+            }
+        }
+
+        pairs
+            .into_iter()
+            .map(|(id, screen_name)| ScreenNameEntry {
+                id,
+                screen_name,
+                snapshots: vec![self.timestamp],
+                source: Some(self.url.clone()),
+            })
+            .collect()
+    }
+}
+
+/// WARC files as an input format, with the capture URL as the source of each observation.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Warc;
+
+impl Importer for Warc {
+    fn name(&self) -> &str {
+        "warc"
+    }
+
+    fn description(&self) -> &str {
+        "WARC files containing archived Twitter pages"
+    }
+
+    fn detect(&self, first_line: &[u8]) -> bool {
+        first_line.trim_ascii_start().starts_with(b"WARC/")
+    }
+
+    /// Invalid records are rejected (numbered by record instead of line), and reading continues
+    /// with the next record.
+    fn entries<'a>(&self, source: Box<dyn BufRead + 'a>) -> Entries<'a> {
+        let mut reader = CaptureReader::new(source);
+
+        let captures = std::iter::from_fn(move || {
+            reader.next().map(|capture| match capture {
+                Ok(capture) => Ok(capture.extract_entries()),
+                Err(Error::InvalidWarcRecord(detail)) => {
+                    Err(memory_lol_import::Error::InvalidLine {
+                        line: reader.records(),
+                        kind: RejectKind::Record,
+                        detail,
+                        contents: String::new(),
+                    })
+                }
+                Err(error) => Err(memory_lol_import::Error::Format(Box::new(error))),
+            })
+        });
+
+        Box::new(captures.flat_map(|result| {
+            let (entries, error) = match result {
+                Ok(entries) => (entries, None),
+                Err(error) => (vec![], Some(error)),
+            };
+
+            entries.into_iter().map(Ok).chain(error.map(Err))
+        }))
+    }
+}
+
+/// Reads the Twitter captures in a WARC file, skipping all other records.
+///
+/// After an invalid record, anything before the next record header is skipped.
+pub struct CaptureReader<R> {
+    reader: R,
+    records: u64,
+    resync: bool,
+}
+
+impl<R: BufRead> CaptureReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            records: 0,
+            resync: false,
+        }
+    }
+
+    /// The number of records read so far (including invalid ones).
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    fn read_line(&mut self, line: &mut String) -> Result<usize, Error> {
+        let mut bytes = vec![];
+        let count = self.reader.read_until(b'\n', &mut bytes)?;
+        *line = String::from_utf8_lossy(&bytes)
+            .trim_end_matches(['\r', '\n'])
+            .to_string();
+
+        Ok(count)
+    }
+
+    fn invalid_record(&mut self, detail: String) -> Error {
+        self.resync = true;
+        Error::InvalidWarcRecord(detail)
+    }
+
+    fn next_capture(&mut self) -> Result<Option<Capture>, Error> {
+        let mut line = String::new();
+
+        loop {
+            // Records are separated by blank lines (other content is reported as an invalid
+            // record, unless it follows one).
+            loop {
+                if self.read_line(&mut line)? == 0 {
+                    return Ok(None);
+                } else if line.starts_with("WARC/") {
+                    break;
+                } else if !line.is_empty() && !self.resync {
+                    self.records += 1;
+                    return Err(self.invalid_record(line));
+                }
+            }
+
+            self.records += 1;
+            self.resync = false;
+
+            let mut record_type = None;
+            let mut target_uri = None;
+            let mut date = None;
+            let mut content_length = None;
+
+            loop {
+                if self.read_line(&mut line)? == 0 {
+                    return Err(self.invalid_record("Unexpected end of file".to_string()));
+                } else if line.is_empty() {
+                    break;
+                }
+
+                if let Some((name, value)) = line.split_once(':') {
+                    let value = value.trim().to_string();
+
+                    match name.trim().to_ascii_lowercase().as_str() {
+                        "warc-type" => record_type = Some(value),
+                        "warc-target-uri" => target_uri = Some(value),
+                        "warc-date" => date = Some(value),
+                        "content-length" => content_length = Some(value),
+                        _ => {}
+                    }
+                }
+            }
+
+            let content_length = match content_length.and_then(|value| value.parse::<u64>().ok()) {
+                Some(content_length) => content_length,
+                None => return Err(self.invalid_record("Missing content length".to_string())),
+            };
+
+            let timestamp = date
+                .and_then(|date| DateTime::parse_from_rfc3339(&date).ok())
+                .map(|timestamp| timestamp.with_timezone(&Utc));
+
+            let url = target_uri
+                .map(|uri| uri.trim_matches(['<', '>']).to_string())
+                .filter(|url| is_twitter_url(url));
+
+            // The content length is not trusted for allocation, since the file may be corrupt.
+            let mut block = (&mut self.reader).take(content_length);
+
+            match (record_type.as_deref(), url, timestamp) {
+                (Some("response"), Some(url), Some(timestamp)) => {
+                    let mut response = vec![];
+
+                    if (block.read_to_end(&mut response)? as u64) < content_length {
+                        return Err(self.invalid_record(format!("Truncated record for {url}")));
+                    }
+
+                    return Ok(Some(Capture {
+                        url,
+                        timestamp,
+                        response,
+                    }));
+                }
+                _ => {
+                    if std::io::copy(&mut block, &mut std::io::sink())? < content_length {
+                        return Err(self.invalid_record("Truncated record".to_string()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for CaptureReader<R> {
+    type Item = Result<Capture, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_capture().transpose()
+    }
+}
+
+fn is_twitter_url(url: &str) -> bool {
+    let without_scheme = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .unwrap_or(url);
+    let host = without_scheme
+        .split(['/', '?', '#'])
+        .next()
+        .unwrap_or_default()
+        .split(':')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+
+    TWITTER_HOSTS.contains(&host.as_str())
+}
+
+/// The content type and decoded body of a successful HTTP response.
+fn parse_response(response: &[u8]) -> Option<(String, Vec<u8>)> {
+    let header_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&response[..header_end]).ok()?;
+    let body = &response[header_end + 4..];
+
+    let mut lines = head.lines();
+    let status = lines.next()?.split_whitespace().nth(1)?;
+
+    if status != "200" {
+        return None;
+    }
+
+    let mut content_type = String::new();
+    let mut chunked = false;
+    let mut gzip = false;
+
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim().to_ascii_lowercase();
+
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-type" => content_type = value,
+                "transfer-encoding" => chunked = value.contains("chunked"),
+                "content-encoding" => gzip = value.contains("gzip"),
+                _ => {}
+            }
+        }
+    }
+
+    let body = if chunked {
+        dechunk(body)?
+    } else {
+        body.to_vec()
+    };
+
+    let body = if gzip {
+        let mut decoded = vec![];
+        GzDecoder::new(body.as_slice())
+            .read_to_end(&mut decoded)
+            .ok()?;
+        decoded
+    } else {
+        body
+    };
+
+    Some((content_type, body))
+}
+
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut result = vec![];
+
+    loop {
+        let line_end = body.windows(2).position(|window| window == b"\r\n")?;
+        let size_line = std::str::from_utf8(&body[..line_end]).ok()?;
+        let size_hex = size_line.split(';').next()?.trim();
+        let size = usize::from_str_radix(size_hex, 16).ok()?;
+        body = &body[line_end + 2..];
+
+        if size == 0 {
+            return Some(result);
+        }
+
+        result.extend_from_slice(body.get(..size)?);
+        body = body.get(size + 2..).unwrap_or_default();
+    }
+}
+
+fn extract_html_users(html: &str, pairs: &mut BTreeSet<(u64, String)>) {
+    for tag in TAG_RE.find_iter(html) {
+        let tag = tag.as_str();
+
+        let id = USER_ID_ATTRIBUTE_RE
+            .captures(tag)
+            .and_then(|captures| captures[1].parse::<u64>().ok());
+        let screen_name = SCREEN_NAME_ATTRIBUTE_RE
+            .captures(tag)
+            .map(|captures| captures[1].to_string());
+
+        if let Some(pair) = id.zip(screen_name) {
+            pairs.insert(pair);
+        }
+
+        if tag.contains("json-data") {
+            if let Some(captures) = VALUE_ATTRIBUTE_RE.captures(tag) {
+                if let Ok(value) = serde_json::from_str(&unescape_html(&captures[1])) {
+                    extract_json_users(&value, pairs);
+                }
+            }
+        }
+    }
+
+    for (index, _) in html.match_indices(INITIAL_STATE_PREFIX) {
+        let rest = &html[index + INITIAL_STATE_PREFIX.len()..];
+
+        if let Some(start) = rest.find('{') {
+            let mut values =
+                serde_json::Deserializer::from_str(&rest[start..]).into_iter::<Value>();
+
+            if let Some(Ok(value)) = values.next() {
+                extract_json_users(&value, pairs);
+            }
+        }
+    }
+}
+
+/// Collect every object that has both an `id_str` and a `screen_name` (user objects and mentions).
+fn extract_json_users(value: &Value, pairs: &mut BTreeSet<(u64, String)>) {
+    match value {
+        Value::Object(fields) => {
+            let id = fields
+                .get("id_str")
+                .and_then(|id| id.as_str())
+                .and_then(|id| id.parse::<u64>().ok());
+            let screen_name = fields
+                .get("screen_name")
+                .and_then(|screen_name| screen_name.as_str());
+
+            if let Some((id, screen_name)) = id.zip(screen_name) {
+                pairs.insert((id, screen_name.to_string()));
+            }
+
+            for value in fields.values() {
+                extract_json_users(value, pairs);
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                extract_json_users(value, pairs);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn unescape_html(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#34;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::{BufReader, Write};
+
+    const HTML_RESPONSE: &str = "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n\
+        <div data-user-id=\"1\" data-screen-name=\"foo\"></div>";
+
+    fn record(record_type: &str, url: &str, block: &[u8]) -> Vec<u8> {
+        let mut record = format!(
+            "WARC/1.0\r\nWARC-Type: {}\r\nWARC-Target-URI: {}\r\n\
+            WARC-Date: 2020-01-01T00:00:00Z\r\nContent-Length: {}\r\n\r\n",
+            record_type,
+            url,
+            block.len()
+        )
+        .into_bytes();
+        record.extend_from_slice(block);
+        record.extend_from_slice(b"\r\n\r\n");
+        record
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn pairs<R: BufRead>(reader: CaptureReader<R>) -> Vec<(u64, String)> {
+        reader
+            .flat_map(|capture| capture.unwrap().extract_entries())
+            .map(|entry| (entry.id, entry.screen_name))
+            .collect()
+    }
+
+    #[test]
+    fn gzip_members() {
+        let mut warc = gzip(&record(
+            "response",
+            "https://twitter.com/foo",
+            HTML_RESPONSE.as_bytes(),
+        ));
+        warc.extend(gzip(&record(
+            "response",
+            "https://twitter.com/bar",
+            HTML_RESPONSE.replace("foo", "bar").as_bytes(),
+        )));
+
+        let reader = crate::input::decompress(BufReader::new(warc.as_slice())).unwrap();
+
+        assert_eq!(
+            pairs(CaptureReader::new(reader)),
+            vec![(1, "foo".to_string()), (1, "bar".to_string())]
+        );
+    }
+
+    #[test]
+    fn chunked_body() {
+        let json = br#"{"user": {"id_str": "2", "screen_name": "baz"}}"#;
+        let mut response =
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nTransfer-Encoding: chunked\r\n\r\n"
+                .to_vec();
+
+        for chunk in json.chunks(10) {
+            response.extend(format!("{:x}\r\n", chunk.len()).into_bytes());
+            response.extend_from_slice(chunk);
+            response.extend_from_slice(b"\r\n");
+        }
+        response.extend_from_slice(b"0\r\n\r\n");
+
+        let warc = record(
+            "response",
+            "https://api.twitter.com/1.1/users/show.json",
+            &response,
+        );
+
+        assert_eq!(
+            pairs(CaptureReader::new(warc.as_slice())),
+            vec![(2, "baz".to_string())]
+        );
+    }
+
+    #[test]
+    fn non_200_response() {
+        let response = HTML_RESPONSE.replace("200 OK", "404 Not Found");
+        let warc = record("response", "https://twitter.com/foo", response.as_bytes());
+        let captures = CaptureReader::new(warc.as_slice())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(captures.len(), 1);
+        assert!(captures[0].extract_entries().is_empty());
+    }
+
+    #[test]
+    fn non_twitter_url() {
+        let mut warc = record(
+            "response",
+            "https://example.com/foo",
+            HTML_RESPONSE.as_bytes(),
+        );
+        warc.extend(record("request", "https://twitter.com/foo", b"GET /foo"));
+
+        assert!(CaptureReader::new(warc.as_slice()).next().is_none());
+    }
+
+    #[test]
+    fn truncated_record() {
+        let warc = b"WARC/1.0\r\nWARC-Type: response\r\nWARC-Target-URI: https://twitter.com/foo\r\n\
+            WARC-Date: 2020-01-01T00:00:00Z\r\nContent-Length: 18446744073709551615\r\n\r\nHTTP/1.1 200";
+
+        assert!(matches!(
+            CaptureReader::new(warc.as_slice()).next(),
+            Some(Err(Error::InvalidWarcRecord(_)))
+        ));
+    }
+
+    #[test]
+    fn invalid_record_recovery() {
+        let mut warc = b"WARC/1.0\r\nWARC-Type: response\r\n\r\nnot a block\r\n\r\n".to_vec();
+        warc.extend(record(
+            "response",
+            "https://twitter.com/foo",
+            HTML_RESPONSE.as_bytes(),
+        ));
+
+        let mut reader = CaptureReader::new(warc.as_slice());
+
+        assert!(matches!(
+            reader.next(),
+            Some(Err(Error::InvalidWarcRecord(_)))
+        ));
+        assert_eq!(reader.records(), 1);
+        assert_eq!(
+            reader.next().unwrap().unwrap().extract_entries()[0].screen_name,
+            "foo"
+        );
+        assert_eq!(reader.records(), 2);
+        assert!(reader.next().is_none());
+    }
+}
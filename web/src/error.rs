@@ -24,12 +24,15 @@ pub enum Error {
     TwitterOAuth(#[from] memory_lol_auth::twitter::Error),
     #[error("Invalid inclusion file line")]
     InvalidInclusionFileLine(String),
+    #[error("Trusted access required")]
+    Untrusted,
 }
 
 impl<'r, 'o: 'r> Responder<'r, 'o> for Error {
     fn respond_to(self, req: &'r Request<'_>) -> Result<'o> {
         match self {
             Error::InvalidSnowflake(_) => Status::NotFound.respond_to(req),
+            Error::Untrusted => Status::Unauthorized.respond_to(req),
             _ => Status::InternalServerError.respond_to(req),
         }
     }
@@ -1,8 +1,8 @@
 use super::{error::Error, inclusions::Inclusions, ExtendedAccount, ExtendedScreenNameResult};
 use chrono::{Duration, NaiveDate, Utc};
 use memory_lol::{
-    db::{table::ReadOnly, Database},
-    model::Account,
+    model::{Account, CaseVariants},
+    store::Store,
 };
 use serde_json::{Map, Value};
 
@@ -14,7 +14,7 @@ fn get_unauthorized_first_date(limit: i64) -> NaiveDate {
 }
 
 fn lookup_ids(
-    db: &Database<ReadOnly>,
+    db: &dyn Store,
     user_ids: &[u64],
     inclusions: &Inclusions,
     earliest: Option<NaiveDate>,
@@ -33,7 +33,12 @@ fn lookup_ids(
                     if result.is_empty() {
                         None
                     } else {
-                        Some(Ok(Account::from_raw_result(*user_id, result).into()))
+                        Some(Ok(Account::from_raw_result(
+                            *user_id,
+                            result,
+                            CaseVariants::Keep,
+                        )
+                        .into()))
                     }
                 }
                 Err(error) => Some(Err(Error::from(error))),
@@ -43,7 +48,7 @@ fn lookup_ids(
 }
 
 pub(crate) fn by_user_id(
-    db: &Database<ReadOnly>,
+    db: &dyn Store,
     user_id: u64,
     is_trusted: bool,
 ) -> Result<ExtendedAccount, Error> {
@@ -56,11 +61,11 @@ pub(crate) fn by_user_id(
         )?
     };
 
-    Ok(Account::from_raw_result(user_id, result).into())
+    Ok(Account::from_raw_result(user_id, result, CaseVariants::Keep).into())
 }
 
 pub(crate) fn by_screen_name(
-    db: &Database<ReadOnly>,
+    db: &dyn Store,
     screen_name: String,
     inclusions: &Inclusions,
     is_trusted: bool,
@@ -118,3 +123,20 @@ pub(crate) fn by_screen_name(
         Ok(serde_json::to_value(result)?)
     }
 }
+
+pub(crate) fn confusables(
+    db: &dyn Store,
+    screen_name: &str,
+    inclusions: &Inclusions,
+) -> Result<Value, Error> {
+    let mut map = Map::new();
+
+    for (screen_name, user_ids) in db.lookup_confusables(screen_name)? {
+        let accounts = lookup_ids(db, &user_ids, inclusions, None)?;
+        let result = ExtendedScreenNameResult { accounts };
+
+        map.insert(screen_name, serde_json::to_value(result)?);
+    }
+
+    Ok(serde_json::to_value(map)?)
+}
@@ -1,8 +1,10 @@
 #[macro_use]
 extern crate rocket;
 
-use memory_lol::db::{table::ReadOnly, Database};
-use memory_lol::model::Account;
+use memory_lol::db::{table::ReadOnly, Database, DatabaseOptions};
+use memory_lol::model::{Account, Seen};
+use memory_lol::snowflake::CreationTime;
+use memory_lol::store::{ReadOnlyStore, Store};
 use memory_lol_auth::{
     model::{
         providers::{GitHub, Google, Twitter},
@@ -28,7 +30,6 @@ mod error;
 mod inclusions;
 mod logic;
 mod snowflake;
-mod util;
 
 use error::Error;
 use inclusions::Inclusions;
@@ -40,6 +41,10 @@ fn provider_fairing<P: IsProvider>() -> impl Fairing {
 #[derive(Deserialize)]
 pub struct AppConfig {
     db: String,
+    #[serde(default)]
+    db_options: DatabaseOptions,
+    /// Compact lookup file to serve instead of the RocksDB database
+    store: Option<String>,
     authorization: String,
     domain: Option<String>,
     default_login_redirect_uri: rocket::http::uri::Reference<'static>,
@@ -68,6 +73,13 @@ pub struct ExtendedAccount {
     pub id: u64,
     pub id_str: String,
     pub screen_names: indexmap::IndexMap<String, Option<Vec<chrono::NaiveDate>>>,
+    #[serde(skip_serializing_if = "indexmap::IndexMap::is_empty")]
+    pub seen: indexmap::IndexMap<String, Seen>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<CreationTime>,
+    #[serde(skip_serializing_if = "indexmap::IndexMap::is_empty")]
+    pub case_variants:
+        indexmap::IndexMap<String, indexmap::IndexMap<String, Option<Vec<chrono::NaiveDate>>>>,
 }
 
 impl From<Account> for ExtendedAccount {
@@ -76,6 +88,9 @@ impl From<Account> for ExtendedAccount {
             id: account.id,
             id_str: account.id.to_string(),
             screen_names: account.screen_names,
+            seen: account.seen,
+            created: account.created,
+            case_variants: account.case_variants,
         }
     }
 }
@@ -87,6 +102,9 @@ struct WithToken<'a> {
 
 type SqliteAuthorizer = Authorizer<SqlxAuthDb>;
 
+/// The lookup backend (either a RocksDB database or a compact file).
+pub type Backend = Box<dyn Store + Send + Sync>;
+
 #[derive(PoolDatabase)]
 #[database("sqlite_auth")]
 pub struct Auth(sqlx::SqlitePool);
@@ -95,7 +113,7 @@ pub struct Auth(sqlx::SqlitePool);
 async fn by_user_id(
     user_id: u64,
     cookies: &CookieJar<'_>,
-    db: &State<Database<ReadOnly>>,
+    db: &State<Backend>,
     inclusions: &State<Inclusions>,
     authorizer: &State<SqliteAuthorizer>,
     connection: Connection<Auth>,
@@ -106,7 +124,7 @@ async fn by_user_id(
         auth::lookup_is_trusted(cookies, authorizer, connection).await?
     };
 
-    let account = crate::logic::by_user_id(db, user_id, full_results)?;
+    let account = crate::logic::by_user_id(db.inner().as_ref(), user_id, full_results)?;
 
     Ok(Json(account))
 }
@@ -115,7 +133,7 @@ async fn by_user_id(
 async fn by_user_id_post(
     user_id: u64,
     with_token: Form<WithToken<'_>>,
-    db: &State<Database<ReadOnly>>,
+    db: &State<Backend>,
     inclusions: &State<Inclusions>,
     authorizer: &State<SqliteAuthorizer>,
     mut connection: Connection<Auth>,
@@ -143,7 +161,7 @@ async fn by_user_id_post(
         }
     };
 
-    let account = crate::logic::by_user_id(db, user_id, full_results)?;
+    let account = crate::logic::by_user_id(db.inner().as_ref(), user_id, full_results)?;
 
     Ok(Json(account))
 }
@@ -152,13 +170,18 @@ async fn by_user_id_post(
 async fn by_screen_name(
     screen_name_query: String,
     cookies: &CookieJar<'_>,
-    db: &State<Database<ReadOnly>>,
+    db: &State<Backend>,
     inclusions: &State<Inclusions>,
     authorizer: &State<SqliteAuthorizer>,
     connection: Connection<Auth>,
 ) -> Result<Json<Value>, Error> {
     let is_trusted = auth::lookup_is_trusted(cookies, authorizer, connection).await?;
-    let result = crate::logic::by_screen_name(db, screen_name_query, inclusions, is_trusted)?;
+    let result = crate::logic::by_screen_name(
+        db.inner().as_ref(),
+        screen_name_query,
+        inclusions,
+        is_trusted,
+    )?;
 
     Ok(Json(result))
 }
@@ -167,7 +190,7 @@ async fn by_screen_name(
 async fn by_screen_name_post(
     screen_name_query: String,
     with_token: Form<WithToken<'_>>,
-    db: &State<Database<ReadOnly>>,
+    db: &State<Backend>,
     inclusions: &State<Inclusions>,
     authorizer: &State<SqliteAuthorizer>,
     mut connection: Connection<Auth>,
@@ -190,11 +213,31 @@ async fn by_screen_name_post(
         }
         Some(authorization) => authorization.is_trusted(),
     };
-    let result = crate::logic::by_screen_name(db, screen_name_query, inclusions, access)?;
+    let result =
+        crate::logic::by_screen_name(db.inner().as_ref(), screen_name_query, inclusions, access)?;
 
     Ok(Json(result))
 }
 
+/// Historical screen names that are confusable with the given one (only for trusted users).
+#[get("/tw/confusables/<screen_name>")]
+async fn confusables(
+    screen_name: String,
+    cookies: &CookieJar<'_>,
+    db: &State<Backend>,
+    inclusions: &State<Inclusions>,
+    authorizer: &State<SqliteAuthorizer>,
+    connection: Connection<Auth>,
+) -> Result<Json<Value>, Error> {
+    if auth::lookup_is_trusted(cookies, authorizer, connection).await? {
+        let result = crate::logic::confusables(db.inner().as_ref(), &screen_name, inclusions)?;
+
+        Ok(Json(result))
+    } else {
+        Err(Error::Untrusted)
+    }
+}
+
 #[launch]
 fn rocket() -> _ {
     rocket::build()
@@ -231,6 +274,7 @@ fn rocket() -> _ {
                 by_user_id_post,
                 by_screen_name,
                 by_screen_name_post,
+                confusables,
                 snowflake::info,
                 auth::login::status,
                 auth::login::logout,
@@ -244,9 +288,17 @@ fn rocket() -> _ {
         )
 }
 
-fn init_db(rocket: &Rocket<Build>) -> Option<Database<ReadOnly>> {
+fn init_db(rocket: &Rocket<Build>) -> Option<Backend> {
     let config = rocket.state::<AppConfig>()?;
-    Database::<ReadOnly>::open(&config.db).ok()
+
+    match &config.store {
+        Some(path) => ReadOnlyStore::open(path)
+            .ok()
+            .map(|store| Box::new(store) as Backend),
+        None => Database::<ReadOnly>::open_with_options(&config.db, &config.db_options)
+            .ok()
+            .map(|db| Box::new(db) as Backend),
+    }
 }
 
 fn init_inclusions(rocket: &Rocket<Build>) -> Option<Inclusions> {
@@ -13,7 +13,10 @@ struct SnowflakeInfo {
 
 #[get("/tw/util/snowflake/<id>")]
 pub fn info(id: i64) -> Result<Json<Value>, Error> {
-    let timestamp = crate::util::snowflake_to_date_time(id).ok_or(Error::InvalidSnowflake(id))?;
+    let timestamp = u64::try_from(id)
+        .ok()
+        .and_then(memory_lol::snowflake::snowflake_to_date_time)
+        .ok_or(Error::InvalidSnowflake(id))?;
 
     Ok(Json(serde_json::to_value(SnowflakeInfo {
         epoch_second: timestamp.timestamp(),